@@ -7,10 +7,11 @@ use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io::Read;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 use walkdir::WalkDir;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -26,13 +27,34 @@ struct SlotSpec {
     mode: String,
     layout_tag: String,
     closing_style: SlotClosingStyle,
+    aliases: Vec<String>,
+    /// Component parameters marked `slot-optional` keep their default content when omitted.
+    optional: bool,
 }
 
 #[derive(Debug, Clone)]
 struct LayoutData {
-    html: String,
+    /// The include-expanded layout compiled once into text and slot holes.
+    template: Vec<TemplateSegment>,
     slots: Vec<SlotSpec>,
     layout_names: HashSet<String>,
+    /// Former slot names declared via `slot-aliases`, mapped to the current name.
+    slot_aliases: HashMap<String, String>,
+}
+
+/// Piece of a compiled layout or component: literal text, or a slot element whose
+/// content a page replaces. `default` is the element's own content, kept when the
+/// slot is not filled or fills only an attribute.
+#[derive(Debug, Clone)]
+enum TemplateSegment {
+    Text(String),
+    Slot {
+        name: String,
+        attr: Option<String>,
+        opening: String,
+        closing: String,
+        default: Vec<TemplateSegment>,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -44,24 +66,497 @@ struct PageSlotContent {
     closing_style: SlotClosingStyle,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum MigrationOp {
+    Rename {
+        from: String,
+        to: String,
+    },
+    Merge {
+        from: String,
+        into: String,
+    },
+    Split {
+        slot: String,
+        to: String,
+        selector: String,
+    },
+    Delete {
+        slot: String,
+    },
+    Retag {
+        slot: String,
+        tag: String,
+    },
+}
+
+const DEPS_USAGE: &str = "Usage: site-compiler deps <src> [file] [build options]
+Build options such as --include-root, --pages, --partials and --passthrough resolve pages and includes as in a build.";
+
+const MIGRATE_USAGE: &str =
+    "Usage: site-compiler migrate <src> <operation> [--layout <path>] [--dry-run] [build options]
+Build options such as --include-root, --pages, --partials and --passthrough select pages as in a build.
+Operations:
+  rename-slot <from> <to>
+  merge-slots <from> <into>
+  split-slot <slot> <new-slot> <selector>
+  delete-slot <slot>
+  retag-slot <slot> <tag>";
+
 const VOID_TAGS: [&str; 14] = [
     "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
     "track", "wbr",
 ];
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum UnknownSlotPolicy {
+    /// Report an error and leave the page unbuilt.
+    #[default]
+    Skip,
+    /// Move unknown providers into a `quarantined-slots` comment and build the page.
+    Comment,
+}
+
+#[derive(Debug, Clone, Default)]
+struct BuildOptions {
+    unknown_slots: UnknownSlotPolicy,
+    /// Component directories, relative to the source root, searched after the
+    /// including file's own directory.
+    include_roots: Vec<PathBuf>,
+    /// Print components and assets nothing uses after each full build.
+    report_unused: bool,
+    /// Fail the build when the unused report is not empty.
+    strict_unused: bool,
+    /// Wrap includes and filled slots in comments naming the file they came from.
+    provenance: bool,
+    /// Copy slot content verbatim from the include-expanded page source instead of
+    /// re-serializing the parsed tree.
+    fidelity: bool,
+    /// Source-relative globs always built as pages.
+    page_globs: Vec<SourceGlob>,
+    /// Source-relative globs never built as pages.
+    partial_globs: Vec<SourceGlob>,
+    /// Source-relative globs of HTML files copied to the output unchanged.
+    passthrough_globs: Vec<SourceGlob>,
+    /// Expand includes in passthrough files instead of copying them byte for byte.
+    passthrough_includes: bool,
+    /// Folder for the build cache; `None` keeps it in a `.site-compiler-cache` folder
+    /// next to the output folder.
+    cache_dir: Option<PathBuf>,
+    /// Neither read nor write the build cache.
+    no_cache: bool,
+}
+
+/// A `--pages`, `--partials` or `--passthrough` glob, compiled once when the
+/// options are parsed.
+#[derive(Debug, Clone)]
+struct SourceGlob {
+    pattern: String,
+    regex: regex::Regex,
+}
+
+impl SourceGlob {
+    fn new(pattern: &str) -> Option<SourceGlob> {
+        Some(SourceGlob {
+            pattern: pattern.to_string(),
+            regex: glob_regex(pattern.trim_start_matches('/'))?,
+        })
+    }
+
+    /// Whether the source-relative, `/`-separated `label` matches.
+    fn matches(&self, label: &str) -> bool {
+        self.regex.is_match(label)
+    }
+}
+
+#[derive(Debug)]
+struct BuildArgs {
+    src_dir: String,
+    out_dir: String,
+    watch: bool,
+    options: BuildOptions,
+}
+
+/// Which files each page, layout and component includes (directly), and which
+/// layout each page resolved to, as canonical paths. Glob includes record the
+/// directory they search, so any file added below it counts as a dependency.
+#[derive(Debug, Default)]
+struct DependencyGraph {
+    includes: HashMap<PathBuf, HashSet<PathBuf>>,
+    page_layouts: HashMap<PathBuf, PathBuf>,
+}
+
+impl DependencyGraph {
+    fn reset(&mut self, file: &Path) {
+        self.includes.remove(file);
+    }
+
+    fn record_include(&mut self, from: &Path, to: &Path) {
+        self.includes
+            .entry(from.to_path_buf())
+            .or_default()
+            .insert(to.to_path_buf());
+    }
+
+    fn transitive_includes(&self, file: &Path) -> Vec<PathBuf> {
+        let mut seen = HashSet::new();
+        let mut pending = vec![file.to_path_buf()];
+        while let Some(current) = pending.pop() {
+            if let Some(targets) = self.includes.get(&current) {
+                for target in targets {
+                    if seen.insert(target.clone()) {
+                        pending.push(target.clone());
+                    }
+                }
+            }
+        }
+        let mut result: Vec<PathBuf> = seen.into_iter().collect();
+        result.sort();
+        result
+    }
+
+    /// Whether `changed` is `file` or anything it includes.
+    fn file_depends_on(&self, file: &Path, changed: &Path) -> bool {
+        file == changed
+            || self
+                .transitive_includes(file)
+                .iter()
+                .any(|dep| changed.starts_with(dep))
+    }
+
+    /// Whether `changed` is `page`, its layout, or anything either of them includes.
+    fn page_depends_on(&self, page: &Path, changed: &Path) -> bool {
+        self.file_depends_on(page, changed)
+            || self
+                .page_layouts
+                .get(page)
+                .map(|layout| self.file_depends_on(layout, changed))
+                .unwrap_or(false)
+    }
+
+    fn is_known_layout(&self, path: &Path) -> bool {
+        self.page_layouts.values().any(|layout| layout == path)
+    }
+
+    /// Pages whose output may change when `changed` is edited. A layout file also
+    /// affects pages below its folder whose current layout sits above it, since it
+    /// is now their nearest one.
+    fn affected_pages(&self, changed: &Path) -> Vec<PathBuf> {
+        let layout_dir = changed
+            .file_name()
+            .and_then(|name| name.to_str())
+            .filter(|name| name.eq_ignore_ascii_case("_layout.html"))
+            .and_then(|_| changed.parent());
+        let now_nearest = |page: &Path| {
+            let Some(dir) = layout_dir else {
+                return false;
+            };
+            let current_dir = self
+                .page_layouts
+                .get(page)
+                .and_then(|layout| layout.parent());
+            page.starts_with(dir)
+                && current_dir
+                    .map(|current| dir.starts_with(current))
+                    .unwrap_or(true)
+        };
+
+        let mut pages: Vec<PathBuf> = self
+            .page_layouts
+            .keys()
+            .filter(|page| self.page_depends_on(page, changed) || now_nearest(page))
+            .cloned()
+            .collect();
+        pages.sort();
+        pages
+    }
+}
+
+/// A console line produced on a worker thread, printed later in a fixed order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LogLine {
+    Out(String),
+    Err(String),
+}
+
+/// Result of compiling one page.
+#[derive(Debug, Default)]
+struct PageReport {
+    log: Vec<LogLine>,
+    ok: bool,
+    /// The page's display path when its source was migrated off slot aliases.
+    migrated: Option<String>,
+    /// Output-relative path and content hash of the file written, on success.
+    output: Option<(PathBuf, Vec<u8>)>,
+}
+
+impl PageReport {
+    fn info(&mut self, line: String) {
+        self.log.push(LogLine::Out(line));
+    }
+
+    fn error(&mut self, line: String) {
+        self.log.push(LogLine::Err(line));
+    }
+}
+
+fn print_log(log: &[LogLine]) {
+    for line in log {
+        match line {
+            LogLine::Out(line) => println!("{}", line),
+            LogLine::Err(line) => eprintln!("{}", line),
+        }
+    }
+}
+
+/// Runs `f` over `items` on up to one thread per core and returns the results in
+/// input order, so output stays deterministic.
+fn parallel_map<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let workers = thread::available_parallelism()
+        .map(|count| count.get())
+        .unwrap_or(1)
+        .min(items.len());
+    if workers <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    let mut results: Vec<(usize, R)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        let Some(item) = items.get(index) else {
+                            break;
+                        };
+                        done.push((index, f(item)));
+                    }
+                    done
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    });
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// State carried between builds of one `Compiler`, so watch cycles can reuse it.
+#[derive(Debug, Default)]
+struct BuildState {
+    /// Canonical paths of non-HTML files spliced into pages or layouts by `<include>`.
+    inlined_assets: HashSet<PathBuf>,
+    /// Inlined assets that no built page or stylesheet references, so they get no
+    /// copy in the output folder. Worked out on full builds; incremental builds only
+    /// drop assets that rebuilt pages or changed stylesheets now reference.
+    inline_only: HashSet<PathBuf>,
+    /// Include-expanded text of each component and layout read during the current
+    /// build, keyed by canonical path and whether the expansion is rooted in a layout
+    /// (which keeps the `slot` elements of nested components). Cleared at the start
+    /// of every build.
+    include_cache: HashMap<(PathBuf, bool), String>,
+    /// Warnings raised on a worker thread while expanding a page or layout, keyed by
+    /// the canonical root file, until that page's report prints them in page order.
+    /// Cleared at the start of every build.
+    deferred_warnings: HashMap<PathBuf, Vec<String>>,
+    /// Compiled layouts keyed by canonical path, kept across watch builds until the
+    /// layout or something it includes changes.
+    layouts: HashMap<PathBuf, Arc<LayoutData>>,
+    /// Whether each HTML file has slot providers, with the modification time and
+    /// size it was classified at.
+    html_kinds: HashMap<PathBuf, (SystemTime, u64, bool)>,
+    deps: DependencyGraph,
+    /// Stamp of every source file and outside include target as of the last build,
+    /// keyed by `stamp_key`. Saved to the build cache.
+    file_stamps: HashMap<PathBuf, FileStamp>,
+    /// Stamp of every copied asset in the output folder as of when it was last
+    /// written or checked, keyed by the same relative path. Saved to the build cache.
+    asset_copies: HashMap<PathBuf, FileStamp>,
+    /// Content hash of every page and passthrough output written successfully,
+    /// keyed by output-relative path. Saved to the build cache.
+    output_hashes: HashMap<PathBuf, Vec<u8>>,
+    /// Page/partial disagreement warnings printed by the previous build.
+    reported_warnings: HashSet<String>,
+    /// The source scan of the previous build, which watch builds that only edit
+    /// known files reuse instead of walking the source folder again.
+    sources: Option<SourceFiles>,
+}
+
+/// Files under the source folder from one directory walk, each list sorted.
+#[derive(Debug, Clone, Default)]
+struct SourceFiles {
+    pages: Vec<PathBuf>,
+    /// HTML files that are not pages: layouts and components.
+    fragments: Vec<PathBuf>,
+    /// HTML files copied as they are (`*.static.html` or `--passthrough`).
+    passthrough: Vec<PathBuf>,
+    /// Everything that is not HTML.
+    assets: Vec<PathBuf>,
+    /// Files whose explicit page/partial rule disagrees with their `for-slot` content.
+    warnings: Vec<(PathBuf, String)>,
+    /// Passthrough files left out because another source writes the same output
+    /// file, each with the error naming both sources.
+    collisions: Vec<(PathBuf, String)>,
+}
+
+impl SourceFiles {
+    fn all_files(&self) -> Vec<PathBuf> {
+        self.pages
+            .iter()
+            .chain(&self.fragments)
+            .chain(&self.passthrough)
+            .chain(&self.assets)
+            .chain(self.collisions.iter().map(|(path, _)| path))
+            .cloned()
+            .collect()
+    }
+}
+
+const CACHE_FILE_NAME: &str = ".site-compiler-cache";
+
+/// Size, modification time and content hash of a file. A file whose size and
+/// modification time still match its stamp is assumed unchanged and not reread.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FileStamp {
+    len: u64,
+    modified: SystemTime,
+    hash: Vec<u8>,
+}
+
+impl FileStamp {
+    /// Tab-separated `len`, `modified` (seconds.nanoseconds) and hex hash fields.
+    fn to_fields(&self) -> String {
+        let modified = self
+            .modified
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        format!(
+            "{}\t{}.{:09}\t{}",
+            self.len,
+            modified.as_secs(),
+            modified.subsec_nanos(),
+            to_hex(&self.hash)
+        )
+    }
+
+    fn from_fields(len: &str, modified: &str, hash: &str) -> Option<FileStamp> {
+        let (secs, nanos) = modified.split_once('.')?;
+        Some(FileStamp {
+            len: len.parse().ok()?,
+            modified: SystemTime::UNIX_EPOCH
+                + Duration::new(secs.parse().ok()?, nanos.parse().ok()?),
+            hash: from_hex(hash)?,
+        })
+    }
+}
+
+/// The saved state of the last build, as read back from the cache file.
+#[derive(Debug, Default)]
+struct BuildCache {
+    file_stamps: HashMap<PathBuf, FileStamp>,
+    asset_copies: HashMap<PathBuf, FileStamp>,
+    output_hashes: HashMap<PathBuf, Vec<u8>>,
+    inlined_assets: HashSet<PathBuf>,
+    inline_only: HashSet<PathBuf>,
+    deps: DependencyGraph,
+}
+
+/// How an HTML file was classified as a page or partial, when a rule decided it
+/// rather than the `for-slot` heuristic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PageRule {
+    PageGlob(String),
+    PartialGlob(String),
+    Underscore,
+    ComponentsFolder,
+}
+
+impl PageRule {
+    fn is_page(&self) -> bool {
+        matches!(self, PageRule::PageGlob(_))
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            PageRule::PageGlob(glob) => format!("--pages {}", glob),
+            PageRule::PartialGlob(glob) => format!("--partials {}", glob),
+            PageRule::Underscore => "underscore prefix".to_string(),
+            PageRule::ComponentsFolder => "components/ folder".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IncludeKind {
+    Html,
+    Svg,
+    Css,
+    Js,
+    Text,
+}
+
+impl IncludeKind {
+    fn from_path(path: &Path) -> Self {
+        let ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase())
+            .unwrap_or_default();
+        match ext.as_str() {
+            "html" | "htm" => IncludeKind::Html,
+            "svg" => IncludeKind::Svg,
+            "css" => IncludeKind::Css,
+            "js" | "mjs" => IncludeKind::Js,
+            _ => IncludeKind::Text,
+        }
+    }
+}
+
 struct Compiler {
     src_dir: PathBuf,
     out_dir: PathBuf,
+    options: BuildOptions,
+    state: Mutex<BuildState>,
 }
 
 #[derive(Debug, Clone)]
 struct ExtractedPageSlot {
     tag: String,
     attributes: HashMap<String, String>,
+    inner_html: String,
     original_html: Option<String>,
     closing_style: SlotClosingStyle,
 }
 
+#[derive(Debug, Default)]
+struct ExtractedProviders {
+    order: Vec<String>,
+    slots: HashMap<String, ExtractedPageSlot>,
+    renamed: Vec<String>,
+    /// Comments and other source text directly above each provider, which move with it.
+    leading: HashMap<String, String>,
+    /// Source text after the last provider.
+    trailing: String,
+}
+
+/// Byte range of an element carrying a `slot` or `for-slot` attribute, and of its
+/// content between the start and end tags (empty for void and self-closing elements).
+/// `depth` counts the elements open around it; top-level elements have depth 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SlotSpan {
+    slot: String,
+    start: usize,
+    end: usize,
+    inner_start: usize,
+    inner_end: usize,
+    depth: usize,
+}
+
 impl PageSlotContent {
     fn render(&self) -> String {
         if let Some(original) = &self.original_html {
@@ -114,44 +609,53 @@ fn is_void_element(tag: &str) -> bool {
     VOID_TAGS.contains(&lower.as_str())
 }
 
-fn determine_closing_style(layout_html: &str, tag: &str, slot_name: &str) -> SlotClosingStyle {
-    let pattern = format!(
-        r#"(?is)<{tag}\b[^>]*\bslot\s*=\s*["']{slot}["'][^>]*>"#,
-        tag = regex::escape(tag),
-        slot = regex::escape(slot_name)
-    );
-
-    if let Ok(re) = regex::Regex::new(&pattern) {
-        if let Some(mat) = re.find(layout_html) {
-            let snippet = mat.as_str().trim_end();
-            if snippet.ends_with("/>") {
-                return SlotClosingStyle::SelfClosing;
-            }
-            if is_void_element(tag) {
-                return SlotClosingStyle::Void;
-            }
-            return SlotClosingStyle::Explicit;
-        }
-    }
-
-    if is_void_element(tag) {
-        SlotClosingStyle::Void
-    } else {
-        SlotClosingStyle::Explicit
+/// Closing style of the first element declaring each slot name, including slots
+/// nested in another slot's default content.
+fn slot_closing_styles(html: &str, styles: &mut HashMap<String, SlotClosingStyle>) {
+    for site in slot_attribute_spans(html, "slot") {
+        let open_tag = &html[site.start..site.inner_start];
+        let tag = open_tag
+            .trim_start_matches('<')
+            .split(|ch: char| ch.is_whitespace() || ch == '/' || ch == '>')
+            .next()
+            .unwrap_or("");
+        let style = if site.inner_start != site.end {
+            SlotClosingStyle::Explicit
+        } else if open_tag.trim_end().ends_with("/>") {
+            SlotClosingStyle::SelfClosing
+        } else if is_void_element(tag) {
+            SlotClosingStyle::Void
+        } else {
+            SlotClosingStyle::Explicit
+        };
+        styles.entry(site.slot.clone()).or_insert(style);
+        slot_closing_styles(&html[site.inner_start..site.inner_end], styles);
     }
 }
 
 fn strip_attribute(fragment: &str, attr: &str) -> String {
-    let pattern = format!(
-        r#"(?i)\s+{attr}\s*=\s*(?:"[^"]*"|'[^']*')"#,
-        attr = regex::escape(attr)
-    );
+    strip_attributes(fragment, &[attr])
+}
 
-    if let Ok(re) = regex::Regex::new(&pattern) {
-        re.replace_all(fragment, "").to_string()
-    } else {
-        fragment.to_string()
+/// Removes every attribute named in `names` (case-insensitively), with the
+/// whitespace before it, from an opening tag.
+fn strip_attributes(fragment: &str, names: &[&str]) -> String {
+    let mut stripped = String::with_capacity(fragment.len());
+    let mut last_end = 0;
+    for attribute in tag_attributes(fragment) {
+        let name = &fragment[attribute.name.clone()];
+        if !names
+            .iter()
+            .any(|candidate| name.eq_ignore_ascii_case(candidate))
+        {
+            continue;
+        }
+        let start = fragment[..attribute.name.start].trim_end().len();
+        stripped.push_str(&fragment[last_end..start]);
+        last_end = attribute.end;
     }
+    stripped.push_str(&fragment[last_end..]);
+    stripped
 }
 
 fn set_attribute_on_tag(tag_fragment: &str, attr: &str, value: &str) -> String {
@@ -178,1187 +682,6025 @@ fn set_attribute_on_tag(tag_fragment: &str, attr: &str, value: &str) -> String {
     result
 }
 
-fn include_tag_regex() -> regex::Regex {
-    regex::Regex::new(
-        r#"(?is)<include\b[^>]*\bsrc\s*=\s*["']([^"']+)["'][^>]*?(?:/\s*>|>\s*</include\s*>)"#,
-    )
-    .unwrap()
+fn collect_slot_specs(html: &str) -> Vec<SlotSpec> {
+    let doc = parse_html().one(html);
+    let mut closing_styles = HashMap::new();
+    slot_closing_styles(html, &mut closing_styles);
+
+    let mut slots = Vec::new();
+    for element in doc.select("[slot]").unwrap() {
+        let node = element.as_node();
+        let attrs = node.as_element().unwrap().attributes.borrow();
+
+        let name = attrs.get("slot").unwrap_or("").to_string();
+        let mode = attrs.get("slot-mode").unwrap_or("html").to_string();
+        let layout_tag = node.as_element().unwrap().name.local.to_string();
+        let closing_style = closing_styles.get(&name).copied().unwrap_or_else(|| {
+            if is_void_element(&layout_tag) {
+                SlotClosingStyle::Void
+            } else {
+                SlotClosingStyle::Explicit
+            }
+        });
+        let aliases = attrs
+            .get("slot-aliases")
+            .map(parse_slot_aliases)
+            .unwrap_or_default();
+
+        slots.push(SlotSpec {
+            name,
+            mode,
+            layout_tag,
+            closing_style,
+            aliases,
+            optional: attrs.contains("slot-optional"),
+        });
+    }
+    slots
 }
 
-fn write_if_changed(path: &Path, contents: &str) -> std::io::Result<bool> {
-    if let Ok(existing) = fs::read_to_string(path) {
-        if existing == contents {
-            return Ok(false);
+/// Splits layout or component markup into static text and slot holes in one pass,
+/// so pages render by concatenation. Elements whose `slot` is not in `slots` stay
+/// literal text.
+fn compile_template(html: &str, slots: &[SlotSpec]) -> Vec<TemplateSegment> {
+    fn push_text(segments: &mut Vec<TemplateSegment>, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        match segments.last_mut() {
+            Some(TemplateSegment::Text(existing)) => existing.push_str(text),
+            _ => segments.push(TemplateSegment::Text(text.to_string())),
         }
     }
 
-    fs::write(path, contents)?;
-    Ok(true)
-}
+    let mut segments = Vec::new();
+    let mut last_end = 0;
+    for site in slot_attribute_spans(html, "slot") {
+        push_text(&mut segments, &html[last_end..site.start]);
+        last_end = site.end;
+
+        let open_tag = &html[site.start..site.inner_start];
+        let inner = &html[site.inner_start..site.inner_end];
+        let closing_tag = &html[site.inner_end..site.end];
+
+        let Some(slot) = slots.iter().find(|slot| slot.name == site.slot) else {
+            push_text(&mut segments, open_tag);
+            for segment in compile_template(inner, slots) {
+                match segment {
+                    TemplateSegment::Text(text) => push_text(&mut segments, &text),
+                    slot => segments.push(slot),
+                }
+            }
+            push_text(&mut segments, closing_tag);
+            continue;
+        };
 
-fn format_with_commas(value: u128) -> String {
-    let digits: Vec<char> = value.to_string().chars().collect();
-    let mut formatted = String::with_capacity(digits.len() + digits.len() / 3);
+        let body = open_tag.trim_end_matches('>').trim_end_matches('/');
+        let ending = &open_tag[body.trim_end().len()..];
+        segments.push(TemplateSegment::Slot {
+            name: slot.name.clone(),
+            attr: slot.mode.strip_prefix("attr:").map(|attr| attr.to_string()),
+            opening: format!("{}{}", strip_slot_markers(body).trim_end(), ending),
+            closing: closing_tag.to_string(),
+            default: compile_template(inner, slots),
+        });
+    }
+    push_text(&mut segments, &html[last_end..]);
+    segments
+}
 
-    for (idx, ch) in digits.iter().rev().enumerate() {
-        if idx > 0 && idx % 3 == 0 {
-            formatted.push(',');
+/// Layout `attr:` slots render as the bare element with the attribute set, without
+/// their default content. Component placeholders keep theirs.
+fn clear_attr_slot_defaults(segments: &mut [TemplateSegment]) {
+    for segment in segments {
+        if let TemplateSegment::Slot { attr, default, .. } = segment {
+            if attr.is_some() {
+                default.clear();
+            } else {
+                clear_attr_slot_defaults(default);
+            }
         }
-        formatted.push(*ch);
     }
-
-    formatted.chars().rev().collect()
 }
 
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
+/// Renders a compiled template. Every element of a slot name takes its entry from
+/// `fills`; unfilled slots keep their default content.
+fn render_template(
+    segments: &[TemplateSegment],
+    fills: &HashMap<String, PageSlotContent>,
+    output: &mut String,
+) {
+    for segment in segments {
+        let TemplateSegment::Slot {
+            name,
+            attr,
+            opening,
+            closing,
+            default,
+        } = segment
+        else {
+            if let TemplateSegment::Text(text) = segment {
+                output.push_str(text);
+            }
+            continue;
+        };
+
+        match (fills.get(name), attr) {
+            // Text content is already escaped; html content is inserted as is.
+            (Some(content), None) => {
+                output.push_str(opening);
+                output.push_str(&content.inner_html);
+            }
+            (content, attr) => {
+                let value = attr
+                    .as_ref()
+                    .zip(content)
+                    .and_then(|(attr, content)| Some((attr, content.attributes.get(attr)?)));
+                match value {
+                    Some((attr, value)) => {
+                        output.push_str(&set_attribute_on_tag(opening, attr, value))
+                    }
+                    None => output.push_str(opening),
+                }
+                render_template(default, fills, output);
+            }
+        }
+        output.push_str(closing);
+    }
+}
 
-    let src_dir_arg = args.get(1).map(|s| s.as_str()).unwrap_or("src");
-    let out_dir_arg = args.get(2).map(|s| s.as_str()).unwrap_or("dist");
-    let watch = args.get(3).map(|s| s.as_str()) == Some("--watch");
+/// Removes the slot declaration attributes from a layout or component opening tag.
+fn strip_slot_markers(tag_fragment: &str) -> String {
+    strip_attributes(
+        tag_fragment,
+        &["slot", "slot-mode", "slot-aliases", "slot-optional"],
+    )
+}
 
-    let src_dir_path = Path::new(src_dir_arg);
-    if !src_dir_path.exists() {
-        eprintln!("[Error] Source directory not found: {}", src_dir_arg);
-        std::process::exit(1);
+/// Moves `<style hoist>` and `<script hoist>` blocks to one place per page, keeping
+/// the first copy of identical blocks in first-seen order. Styles go to a
+/// `<!-- hoist:styles -->` marker or the end of `<head>`, scripts to a
+/// `<!-- hoist:scripts -->` marker or the end of `<body>`.
+fn hoist_assets(html: &str) -> String {
+    static BLOCK_RES: OnceLock<[regex::Regex; 2]> = OnceLock::new();
+    let block_res = BLOCK_RES.get_or_init(|| {
+        ["style", "script"].map(|tag| {
+            regex::Regex::new(&format!(
+                r#"(?is)<{tag}\b((?:[^>"']|"[^"]*"|'[^']*')*)>(.*?)</{tag}\s*>"#
+            ))
+            .unwrap()
+        })
+    });
+
+    let mut output = html.to_string();
+    for ((tag, marker, anchor), block_re) in [
+        ("style", "<!-- hoist:styles -->", "</head>"),
+        ("script", "<!-- hoist:scripts -->", "</body>"),
+    ]
+    .into_iter()
+    .zip(block_res)
+    {
+        output = hoist_blocks(&output, block_re, tag, marker, anchor);
     }
+    output
+}
 
-    let src_dir = src_dir_path
-        .canonicalize()
-        .unwrap_or_else(|_| src_dir_path.to_path_buf());
-    let out_dir = Path::new(out_dir_arg).to_path_buf();
-    let compiler = Compiler {
-        src_dir: src_dir.clone(),
-        out_dir: out_dir.clone(),
-    };
-    compiler.clean_output_dir();
+fn hoist_blocks(
+    html: &str,
+    block_re: &regex::Regex,
+    tag: &str,
+    marker: &str,
+    anchor: &str,
+) -> String {
+    static ATTR_RE: OnceLock<regex::Regex> = OnceLock::new();
+    let attr_re = ATTR_RE.get_or_init(|| {
+        regex::Regex::new(r#"([^\s=/]+)(?:\s*=\s*(?:"[^"]*"|'[^']*'|[^\s>]+))?"#).unwrap()
+    });
+
+    let mut remaining = String::with_capacity(html.len());
+    let mut blocks: Vec<String> = Vec::new();
+    let mut first_position = None;
+    let mut last_end = 0;
+
+    for caps in block_re.captures_iter(html) {
+        let attrs = &caps[1];
+        let mut hoisted = false;
+        let mut kept_attrs = String::new();
+        for attr in attr_re.find_iter(attrs) {
+            let name = attr.as_str().split('=').next().unwrap_or("").trim();
+            if name.eq_ignore_ascii_case("hoist") {
+                hoisted = true;
+            } else {
+                kept_attrs.push(' ');
+                kept_attrs.push_str(attr.as_str());
+            }
+        }
+        if !hoisted {
+            continue;
+        }
 
-    let ok = compiler.build_once(None);
-    if !watch {
-        if !ok {
-            std::process::exit(2);
+        let matched = caps.get(0).unwrap();
+        remaining.push_str(&html[last_end..matched.start()]);
+        first_position.get_or_insert(remaining.len());
+        last_end = matched.end();
+
+        let block = format!("<{tag}{kept_attrs}>{}</{tag}>", &caps[2]);
+        if !blocks.contains(&block) {
+            blocks.push(block);
         }
-        return;
     }
 
-    println!("[Watch] Watching for changes…");
-
-    let src_dir_clone = compiler.src_dir.clone();
-    let out_dir_clone = compiler.out_dir.clone();
-    let pending = Arc::new(Mutex::new(HashSet::<PathBuf>::new()));
-    let pending_clone = Arc::clone(&pending);
+    if blocks.is_empty() {
+        return html.to_string();
+    }
+    remaining.push_str(&html[last_end..]);
 
-    let (tx, rx) = std::sync::mpsc::channel();
+    let joined = blocks.join("\n");
+    if let Some(index) = remaining.find(marker) {
+        remaining.replace_range(index..index + marker.len(), &joined);
+    } else if let Some(index) = remaining.to_ascii_lowercase().find(anchor) {
+        remaining.insert_str(index, &joined);
+    } else {
+        remaining.insert_str(first_position.unwrap_or(0), &joined);
+    }
+    remaining
+}
 
-    let mut watcher =
-        match notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
-            Ok(event) => {
-                for path in event.paths {
-                    let is_tmp = path
-                        .extension()
-                        .and_then(|ext| ext.to_str())
-                        .map(|ext| ext.eq_ignore_ascii_case("tmp"))
-                        .unwrap_or(false);
-                    if is_tmp {
-                        continue;
-                    }
-                    let _ = tx.send(path);
-                }
-            }
-            Err(_) => {}
-        }) {
-            Ok(w) => w,
-            Err(e) => {
-                eprintln!("[Error] {}", e);
-                return;
+/// URLs referenced from HTML attributes (`src`, `href`, `srcset`, ...) and from CSS
+/// `url()` / `@import`, as written.
+fn asset_references(text: &str) -> Vec<String> {
+    static RES: OnceLock<[regex::Regex; 3]> = OnceLock::new();
+    let [attr_re, srcset_re, css_re] = RES.get_or_init(|| {
+        [
+            r#"(?i)\b(?:src|href|poster|data|action|content)\s*=\s*(?:"([^"]*)"|'([^']*)')"#,
+            r#"(?i)\bsrcset\s*=\s*(?:"([^"]*)"|'([^']*)')"#,
+            r#"(?i)url\(\s*(?:"([^"]*)"|'([^']*)'|([^)\s]*))\s*\)|@import\s+(?:"([^"]*)"|'([^']*)')"#,
+        ]
+        .map(|pattern| regex::Regex::new(pattern).unwrap())
+    });
+
+    let mut references = Vec::new();
+    let mut push_captures = |caps: regex::Captures| {
+        if let Some(value) = caps.iter().skip(1).flatten().next() {
+            references.push(value.as_str().trim().to_string());
+        }
+    };
+    attr_re.captures_iter(text).for_each(&mut push_captures);
+    css_re.captures_iter(text).for_each(&mut push_captures);
+
+    for caps in srcset_re.captures_iter(text) {
+        let value = caps.get(1).or_else(|| caps.get(2)).unwrap().as_str();
+        for candidate in value.split(',') {
+            if let Some(url) = candidate.split_whitespace().next() {
+                references.push(url.to_string());
             }
-        };
+        }
+    }
+    references
+}
 
-    let _ = watcher.watch(&src_dir_clone, RecursiveMode::Recursive);
+/// Resolves a local URL against the source-relative folder it appears in, returning
+/// a source-relative path. External, data and fragment-only URLs return `None`.
+fn resolve_asset_reference(base: &Path, reference: &str) -> Option<PathBuf> {
+    let reference = reference.split(['?', '#']).next().unwrap_or("");
+    if reference.is_empty() || reference.starts_with("//") || reference.contains(':') {
+        return None;
+    }
 
-    let mut timer_active = false;
-    let mut last_build = std::time::Instant::now();
+    let joined = match reference.strip_prefix('/') {
+        Some(rooted) => PathBuf::from(rooted),
+        None => base.join(reference),
+    };
 
-    loop {
-        match rx.recv_timeout(Duration::from_millis(150)) {
-            Ok(path) => {
-                let normalized = path.canonicalize().unwrap_or(path.clone());
-                pending_clone.lock().unwrap().insert(normalized);
-                timer_active = true;
-                last_build = std::time::Instant::now();
-            }
-            Err(_) => {
-                if timer_active && last_build.elapsed() >= Duration::from_millis(150) {
-                    let changed_paths = {
-                        let mut guard = pending_clone.lock().unwrap();
-                        guard.drain().collect::<HashSet<PathBuf>>()
-                    };
-                    let compiler = Compiler {
-                        src_dir: src_dir_clone.clone(),
-                        out_dir: out_dir_clone.clone(),
-                    };
-                    compiler.build_once(Some(&changed_paths));
-                    timer_active = false;
-                }
+    let mut resolved = PathBuf::new();
+    for component in joined.components() {
+        match component {
+            std::path::Component::ParentDir => {
+                resolved.pop();
             }
+            std::path::Component::Normal(part) => resolved.push(part),
+            _ => {}
         }
     }
+    Some(resolved)
 }
 
-impl Compiler {
-    fn build_once(&self, changed_paths: Option<&HashSet<PathBuf>>) -> bool {
-        let start = Instant::now();
-        let now = Local::now();
-        println!("[Build] {}", now.format("%H:%M:%S"));
-
-        let _ = fs::create_dir_all(&self.out_dir);
-
-        let mut overall_ok = true;
-        let src_dir_canonical = self
-            .src_dir
-            .canonicalize()
-            .unwrap_or_else(|_| self.src_dir.clone());
-
-        let mut full_rebuild = changed_paths.is_none();
-        if let Some(paths) = changed_paths {
-            if paths.is_empty() {
-                full_rebuild = true;
-            } else if paths.iter().any(|path| self.path_missing_with_retry(path)) {
-                full_rebuild = true;
-            } else if paths.iter().any(|path| self.is_layout_file(path)) {
-                full_rebuild = true;
-            }
-        }
+/// HTML-mode slots whose element parses comments as markup (not `<title>`,
+/// `<textarea>`, `<script>` or `<style>`).
+fn accepts_comment_markers(slot: &SlotSpec) -> bool {
+    slot.mode == "html"
+        && !matches!(
+            slot.closing_style,
+            SlotClosingStyle::SelfClosing | SlotClosingStyle::Void
+        )
+        && !["title", "textarea", "script", "style"]
+            .iter()
+            .any(|tag| slot.layout_tag.eq_ignore_ascii_case(tag))
+}
 
-        if !full_rebuild {
-            for path in self.collect_page_paths() {
-                let rel_path = match path.strip_prefix(&self.src_dir) {
-                    Ok(rel) => rel,
-                    Err(_) => continue,
-                };
-                if !self.out_dir.join(rel_path).exists() {
-                    full_rebuild = true;
-                    break;
-                }
-            }
+/// Inserts `text` after a leading `<!DOCTYPE ...>`, or at the start when there is
+/// none, so the document never drops into quirks mode.
+fn insert_after_doctype(html: &str, text: &str) -> String {
+    let trimmed = html.trim_start();
+    let offset = html.len() - trimmed.len();
+    if trimmed
+        .get(..9)
+        .map(|head| head.eq_ignore_ascii_case("<!doctype"))
+        .unwrap_or(false)
+    {
+        if let Some(end) = trimmed.find('>') {
+            let split = offset + end + 1;
+            return format!("{}\n{}{}", &html[..split], text, &html[split..]);
         }
+    }
+    format!("{}\n{}", text, html)
+}
 
-        if !full_rebuild {
-            if let Some(paths) = changed_paths {
-                if paths.iter().any(|path| self.is_component_html(path)) {
-                    full_rebuild = true;
-                }
-            }
-        }
+/// `name` for a `name.static.html` file.
+fn static_html_stem(path: &Path) -> Option<&str> {
+    let name = path.file_name()?.to_str()?;
+    let suffix = ".static.html";
+    (name.len() > suffix.len() && name[name.len() - suffix.len()..].eq_ignore_ascii_case(suffix))
+        .then(|| &name[..name.len() - suffix.len()])
+}
 
-        if let Some(paths) = changed_paths {
-            for path in paths {
-                if self.path_missing_with_retry(path) {
-                    self.remove_output_for_path(path);
-                }
-            }
-        }
+/// Output path for a source-relative path: `about.static.html` is written as
+/// `about.html`, everything else keeps its path.
+fn output_rel_path(rel: &Path) -> PathBuf {
+    match static_html_stem(rel) {
+        Some(stem) => rel.with_file_name(format!("{}.html", stem)),
+        None => rel.to_path_buf(),
+    }
+}
 
-        let mut page_paths: Vec<PathBuf> = Vec::new();
+fn is_glob_pattern(src: &str) -> bool {
+    src.contains('*') || src.contains('?')
+}
 
-        if full_rebuild {
-            page_paths = self.collect_page_paths();
-        } else if let Some(paths) = changed_paths {
-            let mut seen = HashSet::new();
-            for path in paths {
-                if let Some(page_path) =
-                    self.normalize_watch_path(path, src_dir_canonical.as_path())
-                {
-                    if seen.insert(page_path.clone()) {
-                        page_paths.push(page_path);
-                    }
+/// Converts a `/`-separated glob (`*`, `?`, `**`) into an anchored regex.
+fn glob_regex(pattern: &str) -> Option<regex::Regex> {
+    let mut re = String::from("^");
+    let mut chars = pattern.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    re.push_str("(?:.*/)?");
+                } else {
+                    re.push_str(".*");
                 }
             }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            other => re.push_str(&regex::escape(&other.to_string())),
         }
+    }
+    re.push('$');
+    regex::Regex::new(&re).ok()
+}
 
-        let mut layout_cache = HashMap::new();
-        for path in page_paths {
-            let rel_path = match path.strip_prefix(&self.src_dir) {
-                Ok(rel) => rel.to_path_buf(),
-                Err(_) => continue,
-            };
-            let display_path = rel_path.display().to_string();
+fn strip_xml_prologue(svg: &str) -> String {
+    static RE: OnceLock<regex::Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| {
+        regex::Regex::new(r"(?is)<\?xml.*?\?>|<!DOCTYPE[^>\[]*(?:\[.*?\])?\s*>").unwrap()
+    });
+    re.replace_all(svg, "").trim().to_string()
+}
 
-            if !path.exists() {
-                continue;
-            }
+fn escape_html_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
 
-            let page_html = match fs::read_to_string(&path) {
-                Ok(content) => content,
-                Err(e) => {
-                    eprintln!("[Error] {}", e);
-                    overall_ok = false;
-                    continue;
-                }
-            };
+/// Attributes on an `<include>` tag that configure the include itself rather than
+/// filling component parameters.
+const INCLUDE_RESERVED_ATTRIBUTES: [&str; 6] =
+    ["src", "wrap", "select", "from-slot", "reverse", "limit"];
 
-            let layout = match self.layout_for_page(&path, &mut layout_cache) {
-                Ok(layout) => layout,
-                Err(e) => {
-                    eprintln!("[Error] {}: {}", display_path, e);
-                    overall_ok = false;
-                    continue;
-                }
-            };
+/// Component placeholder that receives the child content of an `<include>`.
+const CHILDREN_SLOT: &str = "children";
 
-            let expanded_page_html = match self.expand_includes_in_html(
-                &page_html,
-                path.parent().unwrap_or(self.src_dir.as_path()),
-                &mut Vec::new(),
-            ) {
-                Ok(content) => content,
-                Err(e) => {
-                    eprintln!("[Error] {}: {}", display_path, e);
-                    overall_ok = false;
-                    continue;
-                }
-            };
+#[derive(Debug, Clone)]
+enum ComponentArg {
+    /// Value of an include attribute.
+    Attribute(String),
+    /// A `for-slot` child of the include, or its remaining child content.
+    Provider(PageSlotContent),
+}
 
-            let page_doc = parse_html().one(page_html.clone());
-            let expanded_page_doc = parse_html().one(expanded_page_html);
+#[derive(Debug, Clone)]
+struct IncludeTag<'a> {
+    start: usize,
+    end: usize,
+    attributes: Vec<(String, String)>,
+    src: String,
+    inner: Option<&'a str>,
+}
 
-            // Extract page slots with metadata for normalization
-            let mut raw_page_slots: HashMap<String, ExtractedPageSlot> = HashMap::new();
-            let mut expanded_inner_html_by_slot: HashMap<String, String> = HashMap::new();
-            let mut page_slot_order: Vec<String> = Vec::new();
+impl IncludeTag<'_> {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(attr, _)| attr == name)
+            .map(|(_, value)| value.as_str())
+    }
 
-            for element in page_doc.select("[for-slot]").unwrap() {
-                let node = element.as_node();
-                let attrs_ref = node.as_element().unwrap().attributes.borrow();
+    fn has_attribute(&self, name: &str) -> bool {
+        self.attribute(name).is_some()
+    }
 
-                if let Some(slot_name) = attrs_ref.get("for-slot") {
-                    if raw_page_slots.contains_key(slot_name) {
-                        continue;
-                    }
+    fn params(&self) -> Vec<(String, ComponentArg)> {
+        self.attributes
+            .iter()
+            .filter(|(name, _)| !INCLUDE_RESERVED_ATTRIBUTES.contains(&name.as_str()))
+            .map(|(name, value)| (name.clone(), ComponentArg::Attribute(value.clone())))
+            .collect()
+    }
+}
 
-                    let slot_name_string = slot_name.to_string();
-                    let tag_name = node.as_element().unwrap().name.local.to_string();
+fn is_self_closing_tag(tag: &str) -> bool {
+    tag.trim_end_matches('>').trim_end().ends_with('/')
+}
 
-                    let mut attributes = HashMap::new();
-                    for (attr_name, attr_value) in attrs_ref.map.iter() {
-                        attributes.insert(attr_name.local.to_string(), attr_value.value.clone());
-                    }
+/// Reads the attributes of an `<include>` opening tag, decoding entities the way
+/// the HTML parser does for layout providers.
+fn include_attributes(open_tag: &str) -> Vec<(String, String)> {
+    let mut tag = explicit_include_tags(open_tag);
+    if !is_self_closing_tag(open_tag) {
+        tag.push_str("</include>");
+    }
+    let doc = parse_html().one(tag);
+    let Ok(include) = doc.select_first("include") else {
+        return Vec::new();
+    };
+    let attrs = include.attributes.borrow();
+    attrs
+        .map
+        .iter()
+        .map(|(name, value)| (name.local.to_string(), value.value.clone()))
+        .collect()
+}
 
-                    let outer_html = self.get_outer_html(node);
-                    let trimmed_outer = outer_html.trim_end();
-                    let lower_outer = trimmed_outer.to_ascii_lowercase();
-                    let closing_probe = format!("</{}>", tag_name.to_ascii_lowercase());
-
-                    let closing_style = if trimmed_outer.ends_with("/>") {
-                        SlotClosingStyle::SelfClosing
-                    } else if lower_outer.contains(&closing_probe) {
-                        SlotClosingStyle::Explicit
-                    } else if is_void_element(&tag_name) {
-                        SlotClosingStyle::Void
-                    } else {
-                        SlotClosingStyle::Explicit
-                    };
+/// Finds top-level `<include src>` tags, pairing each non-self-closing tag with its
+/// balanced `</include>` so includes can wrap child content and other includes.
+fn find_include_tags(html: &str) -> Vec<IncludeTag<'_>> {
+    let open_re = include_tag_regex();
+    static CLOSE_RE: OnceLock<regex::Regex> = OnceLock::new();
+    let close_re = CLOSE_RE.get_or_init(|| regex::Regex::new(r"(?i)</include\s*>").unwrap());
+    let next_open = |from: usize| {
+        open_re
+            .find_iter(&html[from..])
+            .find(|open| !is_self_closing_tag(open.as_str()))
+            .map(|open| (from + open.start(), from + open.end()))
+    };
 
-                    page_slot_order.push(slot_name_string.clone());
-                    raw_page_slots.insert(
-                        slot_name_string,
-                        ExtractedPageSlot {
-                            tag: tag_name,
-                            attributes,
-                            original_html: if outer_html.is_empty() {
-                                None
-                            } else {
-                                Some(outer_html)
-                            },
-                            closing_style,
-                        },
-                    );
-                }
-            }
+    let mut tags = Vec::new();
+    let mut search_from = 0;
+    while let Some(open) = open_re.find_at(html, search_from) {
+        search_from = open.end();
+        let attributes = include_attributes(open.as_str());
+        let Some(src) = attributes
+            .iter()
+            .find(|(name, _)| name == "src")
+            .map(|(_, value)| value.clone())
+        else {
+            continue;
+        };
 
-            for element in expanded_page_doc.select("[for-slot]").unwrap() {
-                let node = element.as_node();
-                let attrs_ref = node.as_element().unwrap().attributes.borrow();
+        if is_self_closing_tag(open.as_str()) {
+            tags.push(IncludeTag {
+                start: open.start(),
+                end: open.end(),
+                attributes,
+                src,
+                inner: None,
+            });
+            continue;
+        }
 
-                if let Some(slot_name) = attrs_ref.get("for-slot") {
-                    if expanded_inner_html_by_slot.contains_key(slot_name) {
-                        continue;
+        let mut depth = 1;
+        let mut cursor = open.end();
+        let mut closing = None;
+        while let Some(close) = close_re.find_at(html, cursor) {
+            match next_open(cursor) {
+                Some((nested_start, nested_end)) if nested_start < close.start() => {
+                    depth += 1;
+                    cursor = nested_end;
+                }
+                _ => {
+                    depth -= 1;
+                    cursor = close.end();
+                    if depth == 0 {
+                        closing = Some((close.start(), close.end()));
+                        break;
                     }
-
-                    expanded_inner_html_by_slot
-                        .insert(slot_name.to_string(), self.get_inner_html(node));
                 }
             }
+        }
 
-            let mut page_slots: HashMap<String, PageSlotContent> = HashMap::new();
-            for (slot_name, raw_slot) in &raw_page_slots {
-                page_slots.insert(
-                    slot_name.clone(),
-                    PageSlotContent {
-                        tag: raw_slot.tag.clone(),
-                        inner_html: expanded_inner_html_by_slot
-                            .get(slot_name)
-                            .cloned()
-                            .unwrap_or_default(),
-                        attributes: raw_slot.attributes.clone(),
-                        original_html: raw_slot.original_html.clone(),
-                        closing_style: raw_slot.closing_style,
-                    },
-                );
-            }
+        // An unclosed include is left in place as literal markup.
+        let Some((close_start, close_end)) = closing else {
+            continue;
+        };
+        let inner = &html[open.end()..close_start];
+        tags.push(IncludeTag {
+            start: open.start(),
+            end: close_end,
+            attributes,
+            src,
+            inner: if inner.trim().is_empty() {
+                None
+            } else {
+                Some(inner)
+            },
+        });
+        search_from = close_end;
+    }
 
-            // Check for unknown slots
-            let mut extra = Vec::new();
-            for slot_name in page_slots.keys() {
-                if !layout.layout_names.contains(slot_name) {
-                    extra.push(slot_name.clone());
-                }
-            }
+    tags
+}
 
-            if !extra.is_empty() {
-                println!(
-                    "[Error] {} has unknown slots: {}",
-                    display_path,
-                    extra.join(", ")
-                );
-                overall_ok = false;
-                continue;
-            }
+/// Rewrites the `for-slot` value on the provider's opening tag, whatever its
+/// quoting, leaving the rest of the source untouched.
+fn rename_for_slot(provider_html: &str, new_name: &str) -> String {
+    let Some(attribute) = tag_attributes(provider_html)
+        .into_iter()
+        .find(|attribute| provider_html[attribute.name.clone()].eq_ignore_ascii_case("for-slot"))
+    else {
+        return provider_html.to_string();
+    };
+    let value = format!("\"{}\"", new_name.replace('"', "&quot;"));
+    let (range, replacement) = match attribute.raw_value {
+        Some(range) => (range, value),
+        None => (
+            attribute.name.end..attribute.name.end,
+            format!("={}", value),
+        ),
+    };
+    let mut renamed = provider_html.to_string();
+    renamed.replace_range(range, &replacement);
+    renamed
+}
 
-            let expected_order: Vec<String> = layout
-                .slots
-                .iter()
-                .filter(|slot| page_slots.contains_key(&slot.name))
-                .map(|slot| slot.name.clone())
-                .collect();
-            let order_changed = page_slot_order != expected_order;
+fn parse_slot_aliases(value: &str) -> Vec<String> {
+    value
+        .split(|ch: char| ch == ',' || ch.is_whitespace())
+        .filter(|alias| !alias.is_empty())
+        .map(|alias| alias.to_string())
+        .collect()
+}
 
-            let mut page_slots_for_merge = page_slots.clone();
-            let mut missing_slots = Vec::new();
-            for slot in &layout.slots {
-                if !page_slots_for_merge.contains_key(&slot.name) {
-                    missing_slots.push(slot.name.clone());
-                    page_slots_for_merge
-                        .insert(slot.name.clone(), self.default_slot_provider(slot));
-                }
-            }
+/// Builds the `quarantined-slots` comment for a normalized page, keeping any
+/// providers already quarantined in `source` ahead of newly quarantined ones.
+fn quarantine_comment(source: &str, blocks: &[String]) -> Option<String> {
+    static RE: OnceLock<regex::Regex> = OnceLock::new();
+    let re =
+        RE.get_or_init(|| regex::Regex::new(r"(?s)<!--\s*quarantined-slots\b(.*?)-->").unwrap());
+    let mut entries: Vec<String> = re
+        .captures_iter(source)
+        .map(|caps| caps[1].trim().replace("\r\n", "\n"))
+        .filter(|body| !body.is_empty())
+        .collect();
+    entries.extend(blocks.iter().map(|block| block.replace("-->", "--&gt;")));
+
+    if entries.is_empty() {
+        None
+    } else {
+        Some(format!(
+            "<!-- quarantined-slots\n{}\n-->",
+            entries.join("\n\n")
+        ))
+    }
+}
 
-            if !missing_slots.is_empty() {
-                println!(
-                    "[Normalize] Added missing slots in {}: {}",
-                    display_path,
-                    missing_slots.join(", ")
-                );
-            }
+/// Finds the source byte ranges of `for-slot` providers, skipping providers nested in
+/// another provider or an `<include>`. Unclosed providers are left out, so callers
+/// fall back to re-serializing them.
+fn provider_source_spans(source: &str) -> Vec<SlotSpan> {
+    slot_attribute_spans(source, "for-slot")
+}
 
-            if order_changed {
-                println!(
-                    "[Normalize] Reordered slots to match layout for {}",
-                    display_path
-                );
-            }
+/// Value of `name` in an opening tag, as written (entities are not decoded).
+fn tag_attribute(open_tag: &str, name: &str) -> Option<String> {
+    tag_attributes(open_tag)
+        .into_iter()
+        .find(|attribute| open_tag[attribute.name.clone()].eq_ignore_ascii_case(name))
+        .map(|attribute| attribute.value(open_tag).to_string())
+}
 
-            let uses_crlf = page_html.contains("\r\n");
-            let had_trailing_newline = page_html.ends_with('\n') || page_html.ends_with("\r\n");
+/// One attribute of an opening tag, as byte ranges into the tag.
+struct TagAttribute {
+    name: Range<usize>,
+    /// The value as written, quotes included.
+    raw_value: Option<Range<usize>>,
+    end: usize,
+}
 
-            let mut normalized_blocks = Vec::new();
-            for slot in &layout.slots {
-                if let Some(content) = page_slots_for_merge.get(&slot.name) {
-                    normalized_blocks.push(content.render());
-                }
-            }
+impl TagAttribute {
+    fn value<'a>(&self, open_tag: &'a str) -> &'a str {
+        let Some(range) = self.raw_value.clone() else {
+            return "";
+        };
+        let raw = &open_tag[range];
+        match raw.chars().next() {
+            Some(quote @ ('"' | '\'')) => raw[1..].strip_suffix(quote).unwrap_or(&raw[1..]),
+            _ => raw,
+        }
+    }
+}
 
-            let normalized_join = normalized_blocks.join("\n\n");
-            let normalized_compare = normalized_join.trim_end_matches('\n').to_string();
+/// Scans the attributes of an opening tag (`<name ...>`, with or without the
+/// closing `>`) by hand, so callers on hot paths compile no regex.
+fn tag_attributes(open_tag: &str) -> Vec<TagAttribute> {
+    let bytes = open_tag.as_bytes();
+    let mut pos = usize::from(bytes.first() == Some(&b'<'));
+    while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() && !b"/>".contains(&bytes[pos]) {
+        pos += 1;
+    }
 
-            let original_compare = page_html
-                .replace("\r\n", "\n")
-                .trim_end_matches('\n')
-                .to_string();
+    let mut attributes = Vec::new();
+    loop {
+        while pos < bytes.len() && (bytes[pos].is_ascii_whitespace() || bytes[pos] == b'/') {
+            pos += 1;
+        }
+        if pos >= bytes.len() || bytes[pos] == b'>' {
+            break;
+        }
 
-            if (order_changed || !missing_slots.is_empty())
-                && normalized_compare != original_compare
-            {
-                let mut final_text = normalized_compare.clone();
-                if had_trailing_newline {
-                    final_text.push('\n');
-                }
-                if uses_crlf {
-                    final_text = final_text.replace("\n", "\r\n");
-                }
+        let name_start = pos;
+        pos += 1;
+        while pos < bytes.len()
+            && !bytes[pos].is_ascii_whitespace()
+            && !b"/>=".contains(&bytes[pos])
+        {
+            pos += 1;
+        }
+        let name = name_start..pos;
 
-                match write_if_changed(&path, &final_text) {
-                    Ok(true) => {
-                        println!("[Normalize] Wrote {}", display_path);
-                    }
-                    Ok(false) => {
-                        // Already up to date; nothing to do.
-                    }
-                    Err(e) => {
-                        eprintln!("[Error] {}", e);
-                        overall_ok = false;
-                        continue;
+        let mut after = pos;
+        while after < bytes.len() && bytes[after].is_ascii_whitespace() {
+            after += 1;
+        }
+        let mut raw_value = None;
+        if bytes.get(after) == Some(&b'=') {
+            after += 1;
+            while after < bytes.len() && bytes[after].is_ascii_whitespace() {
+                after += 1;
+            }
+            let value_start = after;
+            match bytes.get(after) {
+                Some(&quote @ (b'"' | b'\'')) => {
+                    after = open_tag[after + 1..]
+                        .find(quote as char)
+                        .map(|offset| after + offset + 2)
+                        .unwrap_or(bytes.len());
+                }
+                _ => {
+                    while after < bytes.len()
+                        && !bytes[after].is_ascii_whitespace()
+                        && bytes[after] != b'>'
+                    {
+                        after += 1;
                     }
                 }
             }
+            raw_value = Some(value_start..after);
+            pos = after;
+        }
+        attributes.push(TagAttribute {
+            name,
+            raw_value,
+            end: pos,
+        });
+    }
+    attributes
+}
 
-            // Build output by merging page slots into layout (string-based to preserve whitespace)
-            let mut output_html = layout.html.clone();
-
-            for slot in &layout.slots {
-                if let Some(content) = page_slots_for_merge.get(&slot.name) {
-                    output_html = self.merge_slot_string(&output_html, slot, content);
-                }
+/// Single pass over `source` that pairs each element carrying `attribute` with its
+/// end tag, counting nesting and skipping comments and raw-text content. Elements
+/// nested inside a match or an `<include>` are not reported.
+fn slot_attribute_spans(source: &str, attribute: &str) -> Vec<SlotSpan> {
+    let tag_re = markup_token_regex();
+
+    let mut spans = Vec::new();
+    let mut stack: Vec<String> = Vec::new();
+    // (slot, start, inner start, depth) of the open provider, and the depth of an open include.
+    let mut provider: Option<(String, usize, usize, usize)> = None;
+    let mut include_depth: Option<usize> = None;
+    let mut cursor = 0;
+
+    while let Some(caps) = tag_re.captures_at(source, cursor) {
+        let whole = caps.get(0).unwrap();
+        cursor = whole.end();
+
+        if let Some(name) = caps.get(1) {
+            let name = name.as_str().to_ascii_lowercase();
+            if let Some(index) = stack.iter().rposition(|open| *open == name) {
+                stack.truncate(index);
             }
-
-            let dest_path = self.out_dir.join(&rel_path);
-            let _ = fs::create_dir_all(dest_path.parent().unwrap());
-            match write_if_changed(&dest_path, &output_html) {
-                Ok(true) => println!("✔  Built {}", display_path),
-                Ok(false) => println!("- Built {} (unchanged)", display_path),
-                Err(e) => {
-                    eprintln!("[Error] {}", e);
-                    overall_ok = false;
-                    continue;
+            if include_depth
+                .map(|depth| stack.len() <= depth)
+                .unwrap_or(false)
+            {
+                include_depth = None;
+            }
+            if let Some((slot, start, inner_start, depth)) = provider.take() {
+                if stack.len() <= depth {
+                    spans.push(SlotSpan {
+                        slot,
+                        start,
+                        end: whole.end(),
+                        inner_start,
+                        inner_end: whole.start(),
+                        depth,
+                    });
+                } else {
+                    provider = Some((slot, start, inner_start, depth));
                 }
             }
+            continue;
         }
+        let Some(name) = caps.get(2) else {
+            continue;
+        };
 
-        self.copy_assets_diff();
-        let elapsed_ms = start.elapsed().as_millis();
-        println!(
-            "[Build] Complete in {} ms.\n",
-            format_with_commas(elapsed_ms)
-        );
-
-        overall_ok
-    }
-
-    fn collect_page_paths(&self) -> Vec<PathBuf> {
-        let mut page_paths = Vec::new();
+        let name = name.as_str().to_ascii_lowercase();
+        let open_tag = whole.as_str();
+        let slot = if provider.is_none() && include_depth.is_none() {
+            tag_attribute(open_tag, attribute)
+        } else {
+            None
+        };
 
-        for entry in WalkDir::new(&self.src_dir)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.path().is_file())
-        {
-            let path = entry.path().to_path_buf();
-            if self.is_page_html(&path) {
-                page_paths.push(path);
+        let mut end = whole.end();
+        let mut inner_end = whole.end();
+        if is_self_closing_tag(open_tag) || is_void_element(&name) {
+            // Nothing to push.
+        } else if matches!(name.as_str(), "script" | "style" | "textarea" | "title") {
+            let close = format!("</{}", name);
+            (inner_end, end) = match find_ignore_ascii_case(&source[end..], &close) {
+                Some(offset) => (
+                    end + offset,
+                    source[end + offset..]
+                        .find('>')
+                        .map(|gt| end + offset + gt + 1)
+                        .unwrap_or(source.len()),
+                ),
+                None => (source.len(), source.len()),
+            };
+            cursor = end;
+        } else {
+            if name == "include" && include_depth.is_none() {
+                include_depth = Some(stack.len());
+            }
+            if let Some(slot) = slot {
+                provider = Some((slot, whole.start(), whole.end(), stack.len()));
             }
+            stack.push(name);
+            continue;
         }
 
-        page_paths.sort();
-        page_paths
+        if let Some(slot) = slot {
+            spans.push(SlotSpan {
+                slot,
+                start: whole.start(),
+                end,
+                inner_start: whole.end(),
+                inner_end,
+                depth: stack.len(),
+            });
+        }
     }
 
-    fn layout_for_page(
-        &self,
-        page_path: &Path,
-        layout_cache: &mut HashMap<PathBuf, LayoutData>,
-    ) -> Result<LayoutData, String> {
-        let layout_path = self.resolve_layout_path(page_path).ok_or_else(|| {
-            let rel = page_path
-                .strip_prefix(&self.src_dir)
-                .unwrap_or(page_path)
-                .display()
-                .to_string();
-            format!("Missing _layout.html for {}", rel)
-        })?;
+    spans
+}
 
-        let cache_key = layout_path
-            .canonicalize()
-            .unwrap_or_else(|_| layout_path.clone());
-        if let Some(layout) = layout_cache.get(&cache_key) {
-            return Ok(layout.clone());
-        }
+/// Element name of an open tag, as written.
+fn open_tag_name(open_tag: &str) -> &str {
+    open_tag
+        .trim_start_matches('<')
+        .split(|ch: char| ch.is_ascii_whitespace() || ch == '/' || ch == '>')
+        .next()
+        .unwrap_or("")
+}
 
-        let layout = self.load_layout_data(&layout_path)?;
-        layout_cache.insert(cache_key, layout.clone());
-        Ok(layout)
+/// Attributes of an open tag with entities decoded. The tag is re-parsed as a `<div>`
+/// so table and list parts are not dropped for lack of a parent.
+fn open_tag_attributes(open_tag: &str) -> HashMap<String, String> {
+    let rest = &open_tag[1 + open_tag_name(open_tag).len()..];
+    let rest = rest.strip_suffix('>').unwrap_or(rest).trim_end_matches('/');
+    let doc = parse_html().one(format!("<div{}></div>", rest));
+    let Ok(div) = doc.select_first("div") else {
+        return HashMap::new();
+    };
+    let attributes = div.attributes.borrow();
+    attributes
+        .map
+        .iter()
+        .map(|(name, value)| (name.local.to_string(), value.value.clone()))
+        .collect()
+}
+
+/// Parent markup a provider with this tag needs to survive HTML tree building, which
+/// drops table parts outside a table and list items outside a list.
+fn fragment_context(tag: &str) -> Option<(&'static str, &'static str)> {
+    match tag.to_ascii_lowercase().as_str() {
+        "tr" => Some(("<table><tbody>", "</tbody></table>")),
+        "td" | "th" => Some(("<table><tbody><tr>", "</tr></tbody></table>")),
+        "thead" | "tbody" | "tfoot" | "caption" | "colgroup" => Some(("<table>", "</table>")),
+        "col" => Some(("<table><colgroup>", "</colgroup></table>")),
+        "li" => Some(("<ul>", "</ul>")),
+        "dt" | "dd" => Some(("<dl>", "</dl>")),
+        "option" | "optgroup" => Some(("<select>", "</select>")),
+        _ => None,
     }
+}
 
-    fn resolve_layout_path(&self, page_path: &Path) -> Option<PathBuf> {
-        let mut current = page_path.parent()?;
+/// Wraps each provider in the parent markup its layout slot's tag requires (or its own
+/// tag when the slot is unknown) so a full-document parse keeps it in place.
+fn wrap_provider_contexts(source: &str, layout: Option<&LayoutData>) -> String {
+    let mut wrapped = String::with_capacity(source.len());
+    let mut last_end = 0;
+
+    for span in provider_source_spans(source) {
+        let provider_tag = open_tag_name(&source[span.start..span.inner_start]);
+        let layout_tag = layout.and_then(|layout| {
+            let name = layout.slot_aliases.get(&span.slot).unwrap_or(&span.slot);
+            layout
+                .slots
+                .iter()
+                .find(|slot| slot.name == *name)
+                .map(|slot| slot.layout_tag.clone())
+        });
+        let Some((open, close)) = fragment_context(layout_tag.as_deref().unwrap_or(provider_tag))
+        else {
+            continue;
+        };
 
-        loop {
-            if !current.starts_with(&self.src_dir) {
-                return None;
-            }
+        wrapped.push_str(&source[last_end..span.start]);
+        wrapped.push_str(open);
+        wrapped.push_str(&source[span.start..span.end]);
+        wrapped.push_str(close);
+        last_end = span.end;
+    }
 
-            let candidate = current.join("_layout.html");
-            if candidate.exists() {
-                return Some(candidate);
-            }
+    wrapped.push_str(&source[last_end..]);
+    wrapped
+}
 
-            if current == self.src_dir {
-                return None;
-            }
+/// Whether any element in `source` carries `for-slot`, ignoring comments,
+/// script/style text and the child content of includes. Much cheaper than parsing
+/// the file.
+fn has_slot_providers(source: &str) -> bool {
+    if !source
+        .as_bytes()
+        .windows(8)
+        .any(|window| window.eq_ignore_ascii_case(b"for-slot"))
+    {
+        return false;
+    }
 
-            current = current.parent()?;
+    let tag_re = markup_token_regex();
+    let mut include_depth = 0usize;
+    let mut cursor = 0;
+    while let Some(caps) = tag_re.captures_at(source, cursor) {
+        let whole = caps.get(0).unwrap();
+        cursor = whole.end();
+        if let Some(name) = caps.get(1) {
+            if name.as_str().eq_ignore_ascii_case("include") {
+                include_depth = include_depth.saturating_sub(1);
+            }
+            continue;
+        }
+        let Some(name) = caps.get(2) else {
+            continue;
+        };
+        let name = name.as_str().to_ascii_lowercase();
+        if include_depth == 0 && tag_attribute(whole.as_str(), "for-slot").is_some() {
+            return true;
+        }
+        if name == "include" && !is_self_closing_tag(whole.as_str()) {
+            include_depth += 1;
+        } else if matches!(name.as_str(), "script" | "style") {
+            let close = format!("</{}", name);
+            cursor = find_ignore_ascii_case(&source[cursor..], &close)
+                .map(|offset| cursor + offset)
+                .unwrap_or(source.len());
         }
     }
+    false
+}
 
-    fn load_layout_data(&self, layout_path: &Path) -> Result<LayoutData, String> {
-        let layout_html = self.expand_includes_in_file(layout_path)?;
-        let layout_doc = parse_html().one(layout_html.clone());
+/// Removes `quarantined-slots` comments, which normalization re-emits at the end.
+fn strip_quarantine_comments(text: &str) -> String {
+    static RE: OnceLock<regex::Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| regex::Regex::new(r"(?s)<!--\s*quarantined-slots\b.*?-->").unwrap());
+    re.replace_all(text, "").to_string()
+}
 
-        let mut slots = Vec::new();
-        for element in layout_doc.select("[slot]").unwrap() {
-            let node = element.as_node();
-            let attrs = node.as_element().unwrap().attributes.borrow();
+fn explicit_include_tags(html: &str) -> String {
+    static RE: OnceLock<regex::Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| regex::Regex::new(r"(?is)<include\b([^>]*?)\s*/>").unwrap());
+    re.replace_all(html, "<include$1></include>").to_string()
+}
 
-            let name = attrs.get("slot").unwrap_or("").to_string();
-            let mode = attrs.get("slot-mode").unwrap_or("html").to_string();
-            let layout_tag = node.as_element().unwrap().name.local.to_string();
-            let closing_style = determine_closing_style(&layout_html, &layout_tag, &name);
+fn include_tag_regex() -> &'static regex::Regex {
+    static RE: OnceLock<regex::Regex> = OnceLock::new();
+    RE.get_or_init(|| regex::Regex::new(r#"(?is)<include\b(?:[^>"']|"[^"]*"|'[^']*')*>"#).unwrap())
+}
 
-            slots.push(SlotSpec {
-                name,
-                mode,
-                layout_tag,
-                closing_style,
-            });
-        }
+/// Comments, end tags and start tags, for the single-pass markup scanners.
+fn markup_token_regex() -> &'static regex::Regex {
+    static RE: OnceLock<regex::Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        regex::Regex::new(
+            r#"(?s)<!--.*?(?:-->|\z)|</([a-zA-Z][\w:-]*)[^>]*>|<([a-zA-Z][\w:-]*)(?:[^>"']|"[^"]*"|'[^']*')*>"#,
+        )
+        .unwrap()
+    })
+}
 
-        if slots.is_empty() {
-            let rel = layout_path
-                .strip_prefix(&self.src_dir)
-                .unwrap_or(layout_path)
-                .display()
-                .to_string();
-            println!("[Warn] No slots in {}. Nothing to merge.", rel);
+/// Byte offset of the first ASCII-case-insensitive match of `needle`.
+fn find_ignore_ascii_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+fn write_if_changed(path: &Path, contents: impl AsRef<[u8]>) -> std::io::Result<bool> {
+    let contents = contents.as_ref();
+    if let Ok(existing) = fs::read(path) {
+        if existing == contents {
+            return Ok(false);
         }
+    }
 
-        let layout_names = slots.iter().map(|slot| slot.name.clone()).collect();
-        Ok(LayoutData {
-            html: layout_html,
-            slots,
-            layout_names,
-        })
+    fs::write(path, contents)?;
+    Ok(true)
+}
+
+fn join_provider_blocks(blocks: &[String]) -> String {
+    blocks.join("\n\n").trim_end_matches('\n').to_string()
+}
+
+fn comparable_source(source: &str) -> String {
+    source
+        .replace("\r\n", "\n")
+        .trim_end_matches('\n')
+        .to_string()
+}
+
+/// Re-applies the trailing newline and CRLF convention of `original` to normalized text.
+fn restore_line_endings(normalized: &str, original: &str) -> String {
+    let mut text = normalized.to_string();
+    if original.ends_with('\n') {
+        text.push('\n');
     }
+    if original.contains("\r\n") {
+        text = text.replace('\n', "\r\n");
+    }
+    text
+}
 
-    fn is_layout_file(&self, path: &Path) -> bool {
-        path.file_name()
-            .and_then(|name| name.to_str())
-            .map(|name| name.eq_ignore_ascii_case("_layout.html"))
-            .unwrap_or(false)
+fn parse_migration_op(args: &[String]) -> Result<MigrationOp, String> {
+    let name = args.first().map(|s| s.as_str()).unwrap_or("");
+    let operands = &args[args.len().min(1)..];
+    let expected = match name {
+        "rename-slot" | "merge-slots" | "retag-slot" => 2,
+        "split-slot" => 3,
+        "delete-slot" => 1,
+        "" => return Err("Missing migration operation".to_string()),
+        other => return Err(format!("Unknown migration operation: {}", other)),
+    };
+
+    if operands.len() != expected {
+        return Err(format!(
+            "{} expects {} argument(s), got {}",
+            name,
+            expected,
+            operands.len()
+        ));
     }
 
-    fn normalize_watch_path(&self, path: &Path, src_dir_canonical: &Path) -> Option<PathBuf> {
-        let mut candidate = if path.is_absolute() {
-            path.to_path_buf()
-        } else {
-            self.src_dir.join(path)
-        };
+    if matches!(name, "rename-slot" | "merge-slots") && operands[0] == operands[1] {
+        return Err(format!(
+            "{} expects two different slots, got '{}' twice",
+            name, operands[0]
+        ));
+    }
 
-        if let Ok(canonical) = candidate.canonicalize() {
-            candidate = canonical;
-        }
+    let arg = |index: usize| operands[index].clone();
+    Ok(match name {
+        "rename-slot" => MigrationOp::Rename {
+            from: arg(0),
+            to: arg(1),
+        },
+        "merge-slots" => MigrationOp::Merge {
+            from: arg(0),
+            into: arg(1),
+        },
+        "split-slot" => MigrationOp::Split {
+            slot: arg(0),
+            to: arg(1),
+            selector: arg(2),
+        },
+        "delete-slot" => MigrationOp::Delete { slot: arg(0) },
+        _ => MigrationOp::Retag {
+            slot: arg(0),
+            tag: arg(1).to_ascii_lowercase(),
+        },
+    })
+}
 
-        if !candidate.exists() {
-            return None;
+/// Line-based unified diff with three lines of context, used for `migrate --dry-run`.
+fn unified_diff(old: &str, new: &str, label: &str) -> String {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let (n, m) = (old_lines.len(), new_lines.len());
+
+    // Longest common subsequence table, filled from the end.
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old_lines[i] == new_lines[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
         }
+    }
 
-        if !candidate.starts_with(src_dir_canonical) {
-            return None;
+    let mut ops: Vec<(char, &str)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old_lines[i] == new_lines[j] {
+            ops.push((' ', old_lines[i]));
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(('-', old_lines[i]));
+            i += 1;
+        } else {
+            ops.push(('+', new_lines[j]));
+            j += 1;
         }
+    }
 
-        if self.is_layout_file(&candidate) {
-            return None;
+    const CONTEXT: usize = 3;
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (index, (kind, _)) in ops.iter().enumerate() {
+        if *kind == ' ' {
+            continue;
         }
-
-        if !self.is_page_html(&candidate) {
-            return None;
+        let start = index.saturating_sub(CONTEXT);
+        let end = (index + CONTEXT + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
         }
+    }
 
-        Some(candidate)
+    if hunks.is_empty() {
+        return String::new();
     }
 
-    fn is_html_file(&self, path: &Path) -> bool {
-        path.extension()
-            .and_then(|ext| ext.to_str())
-            .map(|ext| ext.eq_ignore_ascii_case("html"))
-            .unwrap_or(false)
+    let mut output = format!("--- a/{label}\n+++ b/{label}\n");
+    for (start, end) in hunks {
+        let old_start = ops[..start].iter().filter(|(k, _)| *k != '+').count();
+        let new_start = ops[..start].iter().filter(|(k, _)| *k != '-').count();
+        let old_len = ops[start..end].iter().filter(|(k, _)| *k != '+').count();
+        let new_len = ops[start..end].iter().filter(|(k, _)| *k != '-').count();
+        output.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            old_start + 1,
+            old_len,
+            new_start + 1,
+            new_len
+        ));
+        for (kind, line) in &ops[start..end] {
+            output.push(*kind);
+            output.push_str(line);
+            output.push('\n');
+        }
     }
+    output
+}
 
-    fn is_page_html(&self, path: &Path) -> bool {
-        if !self.is_html_file(path) {
-            return false;
+fn run_migrate(args: &[String]) -> i32 {
+    let mut positional = Vec::new();
+    let mut layout_arg = None;
+    let mut dry_run = false;
+    let mut options = BuildOptions::default();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            "--layout" => match iter.next() {
+                Some(value) => layout_arg = Some(value.clone()),
+                None => {
+                    eprintln!("[Error] --layout expects a path\n{}", MIGRATE_USAGE);
+                    return 1;
+                }
+            },
+            flag => match parse_build_option(flag, &mut iter, &mut options) {
+                Ok(true) => {}
+                Ok(false) => positional.push(arg.clone()),
+                Err(e) => {
+                    eprintln!("[Error] {}\n{}", e, MIGRATE_USAGE);
+                    return 1;
+                }
+            },
         }
+    }
 
-        if path
-            .file_name()
-            .and_then(|name| name.to_str())
-            .map(|name| name.eq_ignore_ascii_case("_layout.html"))
-            .unwrap_or(false)
-        {
-            return false;
+    let Some(src_dir_arg) = positional.first() else {
+        eprintln!("{}", MIGRATE_USAGE);
+        return 1;
+    };
+    let op = match parse_migration_op(&positional[1..]) {
+        Ok(op) => op,
+        Err(e) => {
+            eprintln!("[Error] {}\n{}", e, MIGRATE_USAGE);
+            return 1;
         }
+    };
 
-        self.html_has_slot_providers(path)
+    let src_dir_path = Path::new(src_dir_arg);
+    if !src_dir_path.exists() {
+        eprintln!("[Error] Source directory not found: {}", src_dir_arg);
+        return 1;
     }
 
-    fn is_component_html(&self, path: &Path) -> bool {
-        self.is_html_file(path)
-            && !path
-                .file_name()
-                .and_then(|name| name.to_str())
-                .map(|name| name.eq_ignore_ascii_case("_layout.html"))
-                .unwrap_or(false)
-            && !self.html_has_slot_providers(path)
+    let compiler = Compiler::new(
+        src_dir_path
+            .canonicalize()
+            .unwrap_or_else(|_| src_dir_path.to_path_buf()),
+        PathBuf::new(),
+        options,
+    );
+    let layout_filter = layout_arg.map(|layout| compiler.src_dir.join(layout));
+
+    let pages = compiler.scan_source_files().pages;
+    match compiler.migrate(&pages, &op, layout_filter.as_deref(), dry_run) {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("[Error] {}", e);
+            2
+        }
     }
+}
 
-    fn path_missing_with_retry(&self, path: &Path) -> bool {
-        if path.exists() {
-            return false;
-        }
-        for _ in 0..3 {
-            thread::sleep(Duration::from_millis(10));
-            if path.exists() {
-                return false;
+fn run_deps(args: &[String]) -> i32 {
+    let mut positional = Vec::new();
+    let mut options = BuildOptions::default();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match parse_build_option(arg, &mut iter, &mut options) {
+            Ok(true) => {}
+            Ok(false) if arg.starts_with("--") => {
+                eprintln!("[Error] Unknown option: {}\n{}", arg, DEPS_USAGE);
+                return 1;
+            }
+            Ok(false) => positional.push(arg),
+            Err(e) => {
+                eprintln!("[Error] {}\n{}", e, DEPS_USAGE);
+                return 1;
             }
         }
-        true
     }
-
-    fn get_inner_html(&self, node: &NodeRef) -> String {
-        // Get the inner HTML by serializing all children
-        let mut result = Vec::new();
-        for child in node.children() {
-            let mut child_html = Vec::new();
-            child.serialize(&mut child_html).ok();
-            result.extend(child_html);
+    let (src_dir_arg, target) = match positional.as_slice() {
+        [src] => (*src, None),
+        [src, target] => (*src, Some(*target)),
+        _ => {
+            eprintln!("{}", DEPS_USAGE);
+            return 1;
         }
-        String::from_utf8_lossy(&result).to_string()
-    }
-
-    fn get_outer_html(&self, node: &NodeRef) -> String {
-        let mut result = Vec::new();
-        node.serialize(&mut result).ok();
-        String::from_utf8_lossy(&result).to_string()
-    }
-
-    fn html_has_slot_providers(&self, path: &Path) -> bool {
-        let html = match fs::read_to_string(path) {
-            Ok(content) => content,
-            Err(_) => return false,
-        };
-
-        let doc = parse_html().one(html);
-        doc.select("[for-slot]")
-            .ok()
-            .and_then(|mut nodes| nodes.next())
-            .is_some()
-    }
+    };
 
-    fn expand_includes_in_file(&self, path: &Path) -> Result<String, String> {
-        self.expand_includes_from_file(path, &mut Vec::new())
+    let src_dir_path = Path::new(src_dir_arg);
+    if !src_dir_path.exists() {
+        eprintln!("[Error] Source directory not found: {}", src_dir_arg);
+        return 1;
     }
 
-    fn expand_includes_from_file(
-        &self,
-        path: &Path,
-        stack: &mut Vec<PathBuf>,
-    ) -> Result<String, String> {
-        let canonical = path
+    let compiler = Compiler::new(
+        src_dir_path
             .canonicalize()
-            .map_err(|e| format!("Failed to read include {}: {}", path.display(), e))?;
+            .unwrap_or_else(|_| src_dir_path.to_path_buf()),
+        PathBuf::new(),
+        options,
+    );
+    let ok = compiler.collect_dependencies(&compiler.scan_source_files().pages);
+    let target_path = target.map(|target| {
+        let path = compiler.src_dir.join(target);
+        path.canonicalize().unwrap_or(path)
+    });
+    let is_component = target_path
+        .as_ref()
+        .map(|path| compiler.is_component_html(path))
+        .unwrap_or(false);
+
+    let state = compiler.state.lock().unwrap();
+    let rel = |path: &Path| {
+        path.strip_prefix(&compiler.src_dir)
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    };
 
-        if let Some(index) = stack.iter().position(|entry| *entry == canonical) {
-            let mut chain = stack[index..]
-                .iter()
-                .map(|entry| entry.display().to_string())
-                .collect::<Vec<_>>();
-            chain.push(canonical.display().to_string());
-            return Err(format!("Include cycle detected: {}", chain.join(" -> ")));
+    if let Some(target_path) = target_path {
+        let pages = state.deps.affected_pages(&target_path);
+        if pages.is_empty() && is_component {
+            eprintln!("[Deps] No page includes {}", rel(&target_path));
         }
+        for page in pages {
+            println!("{}", rel(&page));
+        }
+    } else {
+        let mut pages: Vec<&PathBuf> = state.deps.page_layouts.keys().collect();
+        pages.sort();
+        for page in pages {
+            println!("{}", rel(page));
+            let layout = &state.deps.page_layouts[page];
+            println!("  layout  {}", rel(layout));
+            for dep in state.deps.transitive_includes(layout) {
+                println!("  include {} (via layout)", rel(&dep));
+            }
+            for dep in state.deps.transitive_includes(page) {
+                println!("  include {}", rel(&dep));
+            }
+        }
+    }
 
-        let html = fs::read_to_string(&canonical)
-            .map_err(|e| format!("Failed to read include {}: {}", canonical.display(), e))?;
-
-        stack.push(canonical.clone());
-        let expanded = self.expand_includes_in_html(
-            &html,
-            canonical.parent().unwrap_or(self.src_dir.as_path()),
-            stack,
-        );
-        stack.pop();
-        expanded
+    if ok {
+        0
+    } else {
+        2
     }
+}
 
-    fn expand_includes_in_html(
-        &self,
-        html: &str,
-        current_dir: &Path,
-        stack: &mut Vec<PathBuf>,
-    ) -> Result<String, String> {
-        let include_re = include_tag_regex();
-        let mut result = String::with_capacity(html.len());
-        let mut last_end = 0;
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
 
-        for captures in include_re.captures_iter(html) {
-            let matched = captures.get(0).unwrap();
-            let src = captures.get(1).unwrap().as_str();
-            result.push_str(&html[last_end..matched.start()]);
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
 
-            let include_path = current_dir.join(src);
-            let expanded = self.expand_includes_from_file(&include_path, stack)?;
-            result.push_str(&expanded);
+fn format_with_commas(value: u128) -> String {
+    let digits: Vec<char> = value.to_string().chars().collect();
+    let mut formatted = String::with_capacity(digits.len() + digits.len() / 3);
 
-            last_end = matched.end();
+    for (idx, ch) in digits.iter().rev().enumerate() {
+        if idx > 0 && idx % 3 == 0 {
+            formatted.push(',');
         }
-
-        result.push_str(&html[last_end..]);
-        Ok(result)
+        formatted.push(*ch);
     }
 
-    fn default_slot_provider(&self, slot: &SlotSpec) -> PageSlotContent {
-        let mut attributes: HashMap<String, String> = HashMap::new();
-        attributes.insert("for-slot".to_string(), slot.name.clone());
+    formatted.chars().rev().collect()
+}
 
-        if let Some(attr_name) = slot.mode.strip_prefix("attr:") {
-            attributes.insert(attr_name.to_string(), String::new());
+/// Applies one build option (`--include-root`, `--pages`, ...) to `options`, taking
+/// its value from `iter`. Returns false when `arg` is not a build option.
+fn parse_build_option(
+    arg: &str,
+    iter: &mut std::slice::Iter<'_, String>,
+    options: &mut BuildOptions,
+) -> Result<bool, String> {
+    match arg {
+        "--unknown-slots" => {
+            let value = iter
+                .next()
+                .ok_or_else(|| "--unknown-slots expects skip or comment".to_string())?;
+            options.unknown_slots = match value.as_str() {
+                "skip" => UnknownSlotPolicy::Skip,
+                "comment" => UnknownSlotPolicy::Comment,
+                other => return Err(format!("Unknown --unknown-slots policy: {}", other)),
+            };
         }
-
-        // Keep defaults blank so normalized pages clearly signal fields to fill in.
-        PageSlotContent {
-            tag: slot.layout_tag.clone(),
-            inner_html: String::new(),
-            attributes,
-            original_html: None,
-            closing_style: slot.closing_style,
+        "--include-root" => {
+            let value = iter
+                .next()
+                .ok_or_else(|| "--include-root expects a directory".to_string())?;
+            options.include_roots.push(PathBuf::from(value));
+        }
+        "--pages" | "--partials" | "--passthrough" => {
+            let value = iter
+                .next()
+                .ok_or_else(|| format!("{} expects a glob pattern", arg))?;
+            let glob = SourceGlob::new(value)
+                .ok_or_else(|| format!("Invalid {} pattern: {}", arg, value))?;
+            match arg {
+                "--pages" => options.page_globs.push(glob),
+                "--partials" => options.partial_globs.push(glob),
+                _ => options.passthrough_globs.push(glob),
+            }
+        }
+        "--passthrough-includes" => options.passthrough_includes = true,
+        "--cache-dir" => {
+            let value = iter
+                .next()
+                .ok_or_else(|| "--cache-dir expects a directory".to_string())?;
+            options.cache_dir = Some(PathBuf::from(value));
         }
+        "--no-cache" => options.no_cache = true,
+        "--report-unused" => options.report_unused = true,
+        "--debug" => options.provenance = true,
+        "--fidelity" => options.fidelity = true,
+        "--strict-unused" => {
+            options.report_unused = true;
+            options.strict_unused = true;
+        }
+        _ => return Ok(false),
     }
+    Ok(true)
+}
 
-    fn merge_slot_string(&self, html: &str, slot: &SlotSpec, content: &PageSlotContent) -> String {
-        if matches!(
-            slot.closing_style,
-            SlotClosingStyle::SelfClosing | SlotClosingStyle::Void
-        ) {
-            let pattern = format!(
-                r#"(?is)(<{tag}\b[^>]*\bslot\s*=\s*["']{name}["'][^>]*)(\s*/?>)"#,
-                tag = regex::escape(&slot.layout_tag),
-                name = regex::escape(&slot.name)
-            );
-
-            let re = regex::Regex::new(&pattern).unwrap();
-
-            return re
-                .replace(html, |caps: &regex::Captures| {
-                    let ending = &caps[2];
-                    let without_slot = strip_attribute(&caps[1], "slot");
-                    let without_mode = strip_attribute(&without_slot, "slot-mode");
-                    let opening_tag = format!("{}{}", without_mode.trim_end(), ending);
-
-                    match slot.mode.as_str() {
-                        mode if mode.starts_with("attr:") => {
-                            let attr_name = &mode[5..];
-                            if let Some(value) = content.attributes.get(attr_name) {
-                                set_attribute_on_tag(&opening_tag, attr_name, value)
-                            } else {
-                                opening_tag
-                            }
-                        }
-                        _ => opening_tag,
-                    }
-                })
-                .to_string();
+fn parse_build_args(args: &[String]) -> Result<BuildArgs, String> {
+    let mut positional = Vec::new();
+    let mut watch = false;
+    let mut options = BuildOptions::default();
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--watch" => watch = true,
+            flag if parse_build_option(flag, &mut iter, &mut options)? => {}
+            flag if flag.starts_with("--") => return Err(format!("Unknown option: {}", flag)),
+            _ => positional.push(arg.clone()),
         }
+    }
 
-        // Build the search pattern for the slot element
-        // Match: <tag ...slot="name"...>...</tag>
-        let pattern = format!(
-            r#"(?is)(<{tag}\b[^>]*\bslot\s*=\s*["']{name}["'][^>]*>)(.*?)(</{tag}>)"#,
-            tag = regex::escape(&slot.layout_tag),
-            name = regex::escape(&slot.name)
-        );
-
-        let re = regex::Regex::new(&pattern).unwrap();
+    if positional.len() > 2 {
+        return Err(format!("Unexpected argument: {}", positional[2]));
+    }
 
-        re.replace(html, |caps: &regex::Captures| {
-            let opening_tag = strip_attribute(&caps[1], "slot");
-            let opening_tag = strip_attribute(&opening_tag, "slot-mode");
-            let opening_tag = opening_tag.trim_end().to_string();
-            let closing_tag = &caps[3];
+    let mut positional = positional.into_iter();
+    Ok(BuildArgs {
+        src_dir: positional.next().unwrap_or_else(|| "src".to_string()),
+        out_dir: positional.next().unwrap_or_else(|| "dist".to_string()),
+        watch,
+        options,
+    })
+}
 
-            match slot.mode.as_str() {
-                "text" => {
-                    // For text mode, insert content as plain text
-                    format!("{}{}{}", opening_tag, &content.inner_html, closing_tag)
-                }
-                mode if mode.starts_with("attr:") => {
-                    // For attr mode, copy attribute value from the provider element
-                    let attr_name = &mode[5..];
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
 
-                    if let Some(value) = content.attributes.get(attr_name) {
-                        let tag_with_attr = set_attribute_on_tag(&opening_tag, attr_name, value);
-                        format!("{}{}", tag_with_attr, closing_tag)
-                    } else {
-                        format!("{}{}", opening_tag, closing_tag)
-                    }
-                }
-                _ => {
-                    // For html mode (default), insert content as HTML
-                    format!("{}{}{}", opening_tag, &content.inner_html, closing_tag)
-                }
-            }
-        })
-        .to_string()
+    match args.get(1).map(|s| s.as_str()) {
+        Some("migrate") => std::process::exit(run_migrate(&args[2..])),
+        Some("deps") => std::process::exit(run_deps(&args[2..])),
+        _ => {}
     }
 
-    fn copy_assets_diff(&self) {
-        for entry in WalkDir::new(&self.src_dir)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.path().is_file())
-        {
-            let path = entry.path();
-            let file_name = path.file_name().unwrap().to_string_lossy();
-
-            if file_name.ends_with(".html") {
-                continue;
-            }
-
-            let rel_path = path.strip_prefix(&self.src_dir).unwrap();
-            let dest = self.out_dir.join(rel_path);
+    let BuildArgs {
+        src_dir: src_dir_arg,
+        out_dir: out_dir_arg,
+        watch,
+        options,
+    } = match parse_build_args(&args[1..]) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("[Error] {}", e);
+            std::process::exit(1);
+        }
+    };
 
-            let _ = fs::create_dir_all(dest.parent().unwrap());
+    let src_dir_path = Path::new(&src_dir_arg);
+    if !src_dir_path.exists() {
+        eprintln!("[Error] Source directory not found: {}", src_dir_arg);
+        std::process::exit(1);
+    }
 
-            let needs_copy = if dest.exists() {
-                !self.file_hash_equal(path, &dest)
-            } else {
-                true
-            };
+    let src_dir = src_dir_path
+        .canonicalize()
+        .unwrap_or_else(|_| src_dir_path.to_path_buf());
+    let out_dir = Path::new(&out_dir_arg).to_path_buf();
+    let compiler = Compiler::new(src_dir, out_dir, options);
+    let sources = compiler.scan_source_files();
+    compiler.clean_output_dir(&sources);
+
+    let ok = match compiler.resume_from_cache(&sources) {
+        Some(ok) => ok,
+        None => compiler.build_sources(None, &sources),
+    };
+    if !watch {
+        if !ok {
+            std::process::exit(2);
+        }
+        return;
+    }
 
-            if needs_copy {
-                if let Err(e) = fs::copy(path, &dest) {
-                    eprintln!("[Error] {}", e);
-                } else {
-                    println!("📁 Copied {}", rel_path.display());
+    println!("[Watch] Watching for changes…");
+
+    let src_dir_clone = compiler.src_dir.clone();
+    let pending = Arc::new(Mutex::new(HashSet::<PathBuf>::new()));
+    let pending_clone = Arc::clone(&pending);
+
+    let (tx, rx) = std::sync::mpsc::channel();
+
+    let mut watcher =
+        match notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            if let Ok(event) = res {
+                for path in event.paths {
+                    let is_tmp = path
+                        .extension()
+                        .and_then(|ext| ext.to_str())
+                        .map(|ext| ext.eq_ignore_ascii_case("tmp"))
+                        .unwrap_or(false);
+                    if is_tmp {
+                        continue;
+                    }
+                    let _ = tx.send(path);
+                }
+            }
+        }) {
+            Ok(w) => w,
+            Err(e) => {
+                eprintln!("[Error] {}", e);
+                return;
+            }
+        };
+
+    let _ = watcher.watch(&src_dir_clone, RecursiveMode::Recursive);
+
+    let mut timer_active = false;
+    let mut last_build = std::time::Instant::now();
+
+    loop {
+        match rx.recv_timeout(Duration::from_millis(150)) {
+            Ok(path) => {
+                let normalized = path.canonicalize().unwrap_or(path.clone());
+                pending_clone.lock().unwrap().insert(normalized);
+                timer_active = true;
+                last_build = std::time::Instant::now();
+            }
+            Err(_) => {
+                if timer_active && last_build.elapsed() >= Duration::from_millis(150) {
+                    let changed_paths = {
+                        let mut guard = pending_clone.lock().unwrap();
+                        guard.drain().collect::<HashSet<PathBuf>>()
+                    };
+                    compiler.build_once(Some(&changed_paths));
+                    timer_active = false;
                 }
             }
         }
     }
+}
 
-    fn clean_output_dir(&self) {
-        let expected = self.expected_output_set();
-
-        if !self.out_dir.exists() {
-            let _ = fs::create_dir_all(&self.out_dir);
-            return;
+impl Compiler {
+    fn new(src_dir: PathBuf, out_dir: PathBuf, options: BuildOptions) -> Self {
+        Compiler {
+            src_dir,
+            out_dir,
+            options,
+            state: Mutex::new(BuildState::default()),
         }
+    }
 
-        let mut files_to_remove = Vec::new();
-        for entry in WalkDir::new(&self.out_dir)
-            .min_depth(1)
-            .into_iter()
-            .filter_map(|e| e.ok())
+    fn build_once(&self, changed_paths: Option<&HashSet<PathBuf>>) -> bool {
+        let sources = changed_paths
+            .and_then(|paths| self.rescan_changed(paths))
+            .unwrap_or_else(|| self.scan_source_files());
+        self.build_sources(changed_paths, &sources)
+    }
+
+    /// `build_once` over an existing scan of the source folder.
+    fn build_sources(
+        &self,
+        changed_paths: Option<&HashSet<PathBuf>>,
+        sources: &SourceFiles,
+    ) -> bool {
+        let start = Instant::now();
+        let now = Local::now();
+        println!("[Build] {}", now.format("%H:%M:%S"));
+
+        let _ = fs::create_dir_all(&self.out_dir);
         {
-            let path = entry.path();
-            let rel = match path.strip_prefix(&self.out_dir) {
-                Ok(p) => p.to_path_buf(),
-                Err(_) => continue,
-            };
+            let mut state = self.state.lock().unwrap();
+            state.include_cache.clear();
+            state.deferred_warnings.clear();
+        }
 
-            if path.is_file() && !expected.contains(&rel) {
-                files_to_remove.push(path.to_path_buf());
+        let mut overall_ok = true;
+        let src_dir_canonical = self
+            .src_dir
+            .canonicalize()
+            .unwrap_or_else(|_| self.src_dir.clone());
+
+        let mut full_rebuild = changed_paths.is_none();
+        if let Some(paths) = changed_paths {
+            if paths.is_empty() || paths.iter().any(|path| self.path_missing_with_retry(path)) {
+                full_rebuild = true;
             }
         }
 
-        for file in files_to_remove {
-            let rel = file.strip_prefix(&self.out_dir).unwrap_or(file.as_path());
-            if let Err(e) = fs::remove_file(&file) {
-                eprintln!("[Warn] Failed to remove {}: {}", rel.display(), e);
-            } else {
-                println!("[Cleanup] Removed {}", rel.display());
+        if !full_rebuild {
+            if let Some(paths) = changed_paths {
+                // A new layout can become the nearest one for pages that failed to
+                // resolve any layout, which the dependency graph does not know about.
+                let state = self.state.lock().unwrap();
+                if paths.iter().any(|path| {
+                    let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+                    self.is_layout_file(path) && !state.deps.is_known_layout(&canonical)
+                }) {
+                    full_rebuild = true;
+                }
             }
         }
 
-        // Remove empty directories deepest first
-        let mut dirs: Vec<PathBuf> = WalkDir::new(&self.out_dir)
-            .min_depth(1)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.path().is_dir())
-            .map(|e| e.path().to_path_buf())
-            .collect();
+        self.print_scan_warnings(sources);
+        for (_, error) in &sources.collisions {
+            eprintln!("{}", error);
+            overall_ok = false;
+        }
+        if !full_rebuild {
+            for path in sources.pages.iter().chain(&sources.passthrough) {
+                let rel_path = match path.strip_prefix(&self.src_dir) {
+                    Ok(rel) => rel,
+                    Err(_) => continue,
+                };
+                if !self.out_dir.join(output_rel_path(rel_path)).exists() {
+                    full_rebuild = true;
+                    break;
+                }
+            }
+        }
 
-        dirs.sort_by(|a, b| b.components().count().cmp(&a.components().count()));
+        if let Some(paths) = changed_paths {
+            for path in paths {
+                if self.path_missing_with_retry(path) {
+                    self.remove_output_for_path(path);
+                }
+            }
+        }
 
-        for dir in dirs {
-            if let Ok(mut entries) = fs::read_dir(&dir) {
-                if entries.next().is_none() {
-                    let _ = fs::remove_dir(&dir);
+        let mut page_paths: Vec<PathBuf> = Vec::new();
+        let mut passthrough_paths: Vec<PathBuf> = Vec::new();
+
+        if full_rebuild {
+            // Every page and layout is expanded again, which re-records inlined assets
+            // and dependencies.
+            {
+                let mut state = self.state.lock().unwrap();
+                state.inlined_assets.clear();
+                state.layouts.clear();
+                state.deps = DependencyGraph::default();
+            }
+            page_paths = sources.pages.clone();
+            passthrough_paths = sources.passthrough.clone();
+        } else if let Some(paths) = changed_paths {
+            // A file whose `for-slot` providers were removed is no longer a page, even
+            // though the graph still lists it as one.
+            let current_pages: HashSet<&Path> = sources
+                .pages
+                .iter()
+                .filter_map(|page| page.strip_prefix(&self.src_dir).ok())
+                .collect();
+            let is_current_page = |page: &Path| {
+                page.strip_prefix(&src_dir_canonical)
+                    .map(|rel| current_pages.contains(rel))
+                    .unwrap_or(false)
+            };
+            {
+                let mut state = self.state.lock().unwrap();
+                let BuildState { layouts, deps, .. } = &mut *state;
+                deps.page_layouts.retain(|page, _| is_current_page(page));
+                layouts.retain(|layout, _| {
+                    !paths.iter().any(|path| {
+                        let changed = path.canonicalize().unwrap_or_else(|_| path.clone());
+                        deps.file_depends_on(layout, &changed)
+                    })
+                });
+            }
+            let mut seen = HashSet::new();
+            for path in paths {
+                if let Some(page_path) =
+                    self.normalize_watch_path(path, src_dir_canonical.as_path())
+                {
+                    if seen.insert(page_path.clone()) {
+                        page_paths.push(page_path);
+                    }
+                }
+
+                let changed = path.canonicalize().unwrap_or_else(|_| path.clone());
+                let affected = self.state.lock().unwrap().deps.affected_pages(&changed);
+                for page_path in affected {
+                    if page_path.exists() && seen.insert(page_path.clone()) {
+                        page_paths.push(page_path);
+                    }
                 }
             }
+            page_paths.retain(|page| is_current_page(page));
+
+            let state = self.state.lock().unwrap();
+            passthrough_paths = sources
+                .passthrough
+                .iter()
+                .filter(|file| {
+                    let key = file.canonicalize().unwrap_or_else(|_| file.to_path_buf());
+                    paths.iter().any(|path| {
+                        let changed = path.canonicalize().unwrap_or_else(|_| path.clone());
+                        state.deps.file_depends_on(&key, &changed)
+                    })
+                })
+                .cloned()
+                .collect();
         }
-    }
 
-    fn remove_output_for_path(&self, path: &Path) {
-        if let Some(file_name) = path.file_name().and_then(|n| n.to_str()) {
-            if file_name.eq_ignore_ascii_case("_layout.html") {
-                return;
+        page_paths.sort();
+        let mut reports = parallel_map(&page_paths, |path| self.build_page(path));
+        reports.extend(parallel_map(&passthrough_paths, |path| {
+            self.build_passthrough(path)
+        }));
+        let mut migrated_pages = Vec::new();
+        for (path, report) in page_paths.iter().chain(&passthrough_paths).zip(reports) {
+            print_log(&report.log);
+            overall_ok &= report.ok;
+            migrated_pages.extend(report.migrated);
+
+            let mut state = self.state.lock().unwrap();
+            if let Ok(rel) = path.strip_prefix(&self.src_dir) {
+                state.output_hashes.remove(&output_rel_path(rel));
+            }
+            if let Some((out_rel, hash)) = report.output {
+                state.output_hashes.insert(out_rel, hash);
             }
         }
 
-        let rel_path = if let Ok(rel) = path.strip_prefix(&self.src_dir) {
-            rel.to_path_buf()
-        } else if let Some(name) = path.file_name() {
-            PathBuf::from(name)
+        if !migrated_pages.is_empty() {
+            println!(
+                "[Normalize] Migrated {} page(s) from slot aliases: {}",
+                migrated_pages.len(),
+                migrated_pages.join(", ")
+            );
+        }
+
+        let assets = match changed_paths {
+            Some(paths) if !full_rebuild => {
+                // Inlined assets are rechecked too: a page may have stopped inlining one.
+                let state = self.state.lock().unwrap();
+                let changed: HashSet<&Path> = paths
+                    .iter()
+                    .chain(&state.inlined_assets)
+                    .filter_map(|path| {
+                        path.strip_prefix(&self.src_dir)
+                            .or_else(|_| path.strip_prefix(&src_dir_canonical))
+                            .ok()
+                    })
+                    .collect();
+                sources
+                    .assets
+                    .iter()
+                    .filter(|asset| {
+                        asset
+                            .strip_prefix(&self.src_dir)
+                            .map(|rel| changed.contains(rel))
+                            .unwrap_or(false)
+                    })
+                    .cloned()
+                    .collect()
+            }
+            _ => sources.assets.clone(),
+        };
+        if full_rebuild {
+            let pages: Vec<PathBuf> = sources
+                .pages
+                .iter()
+                .chain(&sources.passthrough)
+                .cloned()
+                .collect();
+            self.update_inline_only(&pages, &sources.assets, true);
         } else {
-            return;
+            let pages: Vec<PathBuf> = page_paths
+                .iter()
+                .chain(&passthrough_paths)
+                .cloned()
+                .collect();
+            self.update_inline_only(&pages, &assets, false);
+        }
+        self.copy_assets_diff(&assets);
+
+        if self.options.report_unused && !self.report_unused(sources) {
+            overall_ok = false;
+        }
+
+        if !self.options.no_cache {
+            let rehash: Vec<PathBuf> = match changed_paths {
+                Some(paths) if !full_rebuild => paths
+                    .iter()
+                    .chain(&page_paths)
+                    .filter(|path| path.is_file())
+                    .cloned()
+                    .collect(),
+                _ => {
+                    let state = self.state.lock().unwrap();
+                    self.stamped_files(sources, &state.deps)
+                }
+            };
+            self.update_file_stamps(&rehash, full_rebuild);
+            self.save_build_cache(sources);
+        }
+
+        let elapsed_ms = start.elapsed().as_millis();
+        println!(
+            "[Build] Complete in {} ms.\n",
+            format_with_commas(elapsed_ms)
+        );
+
+        overall_ok
+    }
+
+    /// Compiles one page: normalizes its source when needed and writes its output.
+    /// Console lines are collected so parallel builds can print them in page order.
+    fn build_page(&self, path: &Path) -> PageReport {
+        let mut report = PageReport {
+            ok: true,
+            ..PageReport::default()
         };
+        let rel_path = match path.strip_prefix(&self.src_dir) {
+            Ok(rel) => rel.to_path_buf(),
+            Err(_) => return report,
+        };
+        let display_path = rel_path.display().to_string();
 
-        let dest = self.out_dir.join(&rel_path);
-        if !dest.exists() {
-            return;
+        if !path.exists() {
+            return report;
         }
 
-        let result = if dest.is_dir() {
-            fs::remove_dir_all(&dest)
+        let page_html = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                report.error(format!("[Error] {}", e));
+                report.ok = false;
+                return report;
+            }
+        };
+
+        let page_key = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let layout = self.layout_for_page(path);
+        for line in self.take_warnings(&page_key) {
+            report.info(line);
+        }
+        let layout = match layout {
+            Ok(layout) => layout,
+            Err(e) => {
+                report.error(format!("[Error] {}: {}", display_path, e));
+                report.ok = false;
+                return report;
+            }
+        };
+
+        self.state.lock().unwrap().deps.reset(&page_key);
+        let expanded_page_html = self.expand_includes_in_html(
+            &page_html,
+            path.parent().unwrap_or(self.src_dir.as_path()),
+            &mut vec![page_key.clone()],
+        );
+        for line in self.take_warnings(&page_key) {
+            report.info(line);
+        }
+        let expanded_page_html = match expanded_page_html {
+            Ok(content) => content,
+            Err(e) => {
+                report.error(format!("[Error] {}: {}", display_path, e));
+                report.ok = false;
+                return report;
+            }
+        };
+
+        let page_doc = parse_html().one(explicit_include_tags(&wrap_provider_contexts(
+            &page_html,
+            Some(layout.as_ref()),
+        )));
+        let verbatim_spans = if self.options.fidelity {
+            provider_source_spans(&expanded_page_html)
         } else {
-            fs::remove_file(&dest)
+            Vec::new()
         };
+        let expanded_page_doc = parse_html().one(wrap_provider_contexts(
+            &expanded_page_html,
+            Some(layout.as_ref()),
+        ));
+
+        // Extract page slots with metadata for normalization
+        let ExtractedProviders {
+            order: mut page_slot_order,
+            slots: raw_page_slots,
+            renamed: renamed_slots,
+            leading: leading_text,
+            trailing: trailing_text,
+        } = self.extract_page_providers(&page_html, &page_doc, &layout.slot_aliases);
+        let mut expanded_inner_html_by_slot: HashMap<String, String> = HashMap::new();
+
+        for span in &verbatim_spans {
+            let slot_name = layout
+                .slot_aliases
+                .get(&span.slot)
+                .cloned()
+                .unwrap_or_else(|| span.slot.clone());
+            expanded_inner_html_by_slot
+                .entry(slot_name)
+                .or_insert_with(|| {
+                    expanded_page_html[span.inner_start..span.inner_end].to_string()
+                });
+        }
 
-        match result {
-            Ok(_) => println!("[Cleanup] Removed {}", rel_path.display()),
-            Err(e) => eprintln!("[Error] Failed to remove {}: {}", rel_path.display(), e),
+        for element in expanded_page_doc.select("[for-slot]").unwrap() {
+            let node = element.as_node();
+            let attrs_ref = node.as_element().unwrap().attributes.borrow();
+
+            if let Some(slot_name) = attrs_ref.get("for-slot") {
+                let slot_name = layout
+                    .slot_aliases
+                    .get(slot_name)
+                    .map(|name| name.as_str())
+                    .unwrap_or(slot_name);
+                if expanded_inner_html_by_slot.contains_key(slot_name) {
+                    continue;
+                }
+
+                expanded_inner_html_by_slot
+                    .insert(slot_name.to_string(), self.get_inner_html(node));
+            }
         }
-    }
 
-    fn expected_output_set(&self) -> HashSet<PathBuf> {
-        let mut expected = HashSet::new();
+        let mut page_slots: HashMap<String, PageSlotContent> = HashMap::new();
+        for (slot_name, raw_slot) in &raw_page_slots {
+            page_slots.insert(
+                slot_name.clone(),
+                PageSlotContent {
+                    tag: raw_slot.tag.clone(),
+                    inner_html: expanded_inner_html_by_slot
+                        .get(slot_name)
+                        .cloned()
+                        .unwrap_or_default(),
+                    attributes: raw_slot.attributes.clone(),
+                    original_html: raw_slot.original_html.clone(),
+                    closing_style: raw_slot.closing_style,
+                },
+            );
+        }
+
+        // Check for unknown slots
+        let extra: Vec<String> = page_slot_order
+            .iter()
+            .filter(|slot_name| !layout.layout_names.contains(*slot_name))
+            .cloned()
+            .collect();
+
+        let mut quarantined_blocks = Vec::new();
+        if !extra.is_empty() {
+            match self.options.unknown_slots {
+                UnknownSlotPolicy::Skip => {
+                    report.info(format!(
+                        "[Error] {} has unknown slots: {}",
+                        display_path,
+                        extra.join(", ")
+                    ));
+                    report.ok = false;
+                    return report;
+                }
+                UnknownSlotPolicy::Comment => {
+                    report.info(format!(
+                        "[Warn] {} has unknown slots: {} (quarantined in a comment)",
+                        display_path,
+                        extra.join(", ")
+                    ));
+                    for slot_name in &extra {
+                        if let Some(content) = page_slots.remove(slot_name) {
+                            quarantined_blocks.push(content.render());
+                        }
+                    }
+                    page_slot_order.retain(|slot_name| !extra.contains(slot_name));
+                }
+            }
+        }
+
+        let expected_order: Vec<String> = layout
+            .slots
+            .iter()
+            .filter(|slot| page_slots.contains_key(&slot.name))
+            .map(|slot| slot.name.clone())
+            .collect();
+        let order_changed = page_slot_order != expected_order;
+
+        let mut page_slots_for_merge = page_slots.clone();
+        let mut missing_slots = Vec::new();
+        for slot in &layout.slots {
+            if !page_slots_for_merge.contains_key(&slot.name) {
+                missing_slots.push(slot.name.clone());
+                page_slots_for_merge.insert(slot.name.clone(), self.default_slot_provider(slot));
+            }
+        }
+
+        if !missing_slots.is_empty() {
+            report.info(format!(
+                "[Normalize] Added missing slots in {}: {}",
+                display_path,
+                missing_slots.join(", ")
+            ));
+        }
+
+        if order_changed {
+            report.info(format!(
+                "[Normalize] Reordered slots to match layout for {}",
+                display_path
+            ));
+        }
+
+        if !renamed_slots.is_empty() {
+            report.info(format!(
+                "[Normalize] Renamed slot aliases in {}: {}",
+                display_path,
+                renamed_slots.join(", ")
+            ));
+        }
+
+        let mut normalized_blocks = Vec::new();
+        for slot in &layout.slots {
+            if let Some(content) = page_slots_for_merge.get(&slot.name) {
+                let leading = leading_text
+                    .get(&slot.name)
+                    .map(|text| strip_quarantine_comments(text))
+                    .filter(|text| !text.trim().is_empty())
+                    .map(|text| text.trim_start().to_string())
+                    .unwrap_or_default();
+                normalized_blocks.push(format!("{}{}", leading, content.render()));
+            }
+        }
+        let trailing_text = strip_quarantine_comments(&trailing_text);
+        if !trailing_text.trim().is_empty() {
+            normalized_blocks.push(trailing_text.trim().to_string());
+        }
+
+        if let Some(comment) = quarantine_comment(&page_html, &quarantined_blocks) {
+            normalized_blocks.push(comment);
+        }
+
+        let normalized_compare = join_provider_blocks(&normalized_blocks);
+        let original_compare = comparable_source(&page_html);
+
+        if (order_changed
+            || !missing_slots.is_empty()
+            || !renamed_slots.is_empty()
+            || !quarantined_blocks.is_empty())
+            && normalized_compare != original_compare
+        {
+            let final_text = restore_line_endings(&normalized_compare, &page_html);
+
+            match write_if_changed(path, &final_text) {
+                Ok(true) => {
+                    report.info(format!("[Normalize] Wrote {}", display_path));
+                    if !renamed_slots.is_empty() {
+                        report.migrated = Some(display_path.clone());
+                    }
+                }
+                Ok(false) => {
+                    // Already up to date; nothing to do.
+                }
+                Err(e) => {
+                    report.error(format!("[Error] {}", e));
+                    report.ok = false;
+                    return report;
+                }
+            }
+        }
+
+        // Build output by merging page slots into layout (string-based to preserve whitespace)
+        if self.options.provenance {
+            for slot in layout
+                .slots
+                .iter()
+                .filter(|slot| accepts_comment_markers(slot))
+            {
+                if let Some(content) = page_slots_for_merge.get_mut(&slot.name) {
+                    content.inner_html = format!(
+                        "<!-- begin slot {name} from {page} -->{inner}<!-- end slot {name} -->",
+                        name = slot.name,
+                        page = display_path,
+                        inner = content.inner_html
+                    );
+                }
+            }
+        }
+        let mut output_html = String::new();
+        render_template(&layout.template, &page_slots_for_merge, &mut output_html);
+        if self.options.provenance {
+            if let Some(layout_path) = self.resolve_layout_path(path) {
+                let comment = format!(
+                    "<!-- page {} with layout {} -->",
+                    display_path,
+                    self.source_label(&layout_path)
+                );
+                output_html = insert_after_doctype(&output_html, &comment);
+            }
+        }
+        output_html = hoist_assets(&output_html);
+
+        let dest_path = self.out_dir.join(&rel_path);
+        let _ = fs::create_dir_all(dest_path.parent().unwrap());
+        match write_if_changed(&dest_path, &output_html) {
+            Ok(true) => report.info(format!("✔  Built {}", display_path)),
+            Ok(false) => report.info(format!("- Built {} (unchanged)", display_path)),
+            Err(e) => {
+                report.error(format!("[Error] {}", e));
+                report.ok = false;
+                return report;
+            }
+        }
+        report.output = Some((rel_path, Sha256::digest(output_html.as_bytes()).to_vec()));
+
+        report
+    }
+
+    /// Collects the providers of a page. `source` is the unparsed page so existing
+    /// providers keep their exact markup, plus the comments above them.
+    fn extract_page_providers(
+        &self,
+        source: &str,
+        page_doc: &NodeRef,
+        slot_aliases: &HashMap<String, String>,
+    ) -> ExtractedProviders {
+        let mut extracted = ExtractedProviders::default();
+        let spans = provider_source_spans(source);
+        // Span index of each extracted provider that was found in the source.
+        let mut used_spans: HashMap<usize, String> = HashMap::new();
+
+        for element in page_doc.select("[for-slot]").unwrap() {
+            let node = element.as_node();
+            // `for-slot` children of an include fill component placeholders, not layout slots.
+            let inside_include = node.ancestors().any(|ancestor| {
+                ancestor
+                    .as_element()
+                    .map(|el| &*el.name.local == "include")
+                    .unwrap_or(false)
+            });
+            if inside_include {
+                continue;
+            }
+            let attrs_ref = node.as_element().unwrap().attributes.borrow();
+
+            if let Some(slot_name) = attrs_ref.get("for-slot") {
+                let canonical_name = slot_aliases.get(slot_name);
+                let slot_name_string = canonical_name
+                    .cloned()
+                    .unwrap_or_else(|| slot_name.to_string());
+                if extracted.slots.contains_key(&slot_name_string) {
+                    continue;
+                }
+
+                let tag_name = node.as_element().unwrap().name.local.to_string();
+
+                let mut attributes = HashMap::new();
+                for (attr_name, attr_value) in attrs_ref.map.iter() {
+                    attributes.insert(attr_name.local.to_string(), attr_value.value.clone());
+                }
+
+                let span = spans.iter().enumerate().find(|(index, span)| {
+                    span.slot == *slot_name && !used_spans.contains_key(index)
+                });
+                let mut outer_html = match span {
+                    Some((index, span)) => {
+                        used_spans.insert(index, slot_name_string.clone());
+                        source[span.start..span.end].to_string()
+                    }
+                    None => self.get_outer_html(node),
+                };
+                if let Some(new_name) = canonical_name {
+                    extracted
+                        .renamed
+                        .push(format!("{} -> {}", slot_name, new_name));
+                    attributes.insert("for-slot".to_string(), new_name.clone());
+                    outer_html = rename_for_slot(&outer_html, new_name);
+                }
+                let trimmed_outer = outer_html.trim_end();
+                let lower_outer = trimmed_outer.to_ascii_lowercase();
+                let closing_probe = format!("</{}>", tag_name.to_ascii_lowercase());
+
+                let closing_style = if trimmed_outer.ends_with("/>") {
+                    SlotClosingStyle::SelfClosing
+                } else if lower_outer.contains(&closing_probe) {
+                    SlotClosingStyle::Explicit
+                } else if is_void_element(&tag_name) {
+                    SlotClosingStyle::Void
+                } else {
+                    SlotClosingStyle::Explicit
+                };
+
+                extracted.order.push(slot_name_string.clone());
+                extracted.slots.insert(
+                    slot_name_string,
+                    ExtractedPageSlot {
+                        tag: tag_name,
+                        attributes,
+                        inner_html: self.get_inner_html(node),
+                        original_html: if outer_html.is_empty() {
+                            None
+                        } else {
+                            Some(outer_html)
+                        },
+                        closing_style,
+                    },
+                );
+            }
+        }
+
+        // The parser moves head elements such as `<title>` and `<meta>` ahead of body
+        // content; keep the order they were written in.
+        let source_starts: HashMap<&String, usize> = used_spans
+            .iter()
+            .map(|(index, name)| (name, spans[*index].start))
+            .collect();
+        extracted
+            .order
+            .sort_by_key(|name| source_starts.get(name).copied().unwrap_or(usize::MAX));
+
+        if used_spans.len() == spans.len() {
+            let mut last_end = 0;
+            for (index, span) in spans.iter().enumerate() {
+                let name = &used_spans[&index];
+                let gap = &source[last_end..span.start];
+                if !gap.trim().is_empty() {
+                    extracted
+                        .leading
+                        .insert(name.clone(), gap.trim_start().to_string());
+                }
+                last_end = span.end;
+            }
+            extracted.trailing = source[last_end..].trim().to_string();
+        }
+        extracted
+    }
+
+    /// Applies `op` to each of `pages` (optionally only those governed by
+    /// `layout_filter`) and returns the relative paths of pages that changed or would
+    /// change.
+    fn migrate(
+        &self,
+        pages: &[PathBuf],
+        op: &MigrationOp,
+        layout_filter: Option<&Path>,
+        dry_run: bool,
+    ) -> Result<Vec<String>, String> {
+        let layout_filter = match layout_filter {
+            Some(layout) => Some(
+                layout
+                    .canonicalize()
+                    .map_err(|e| format!("Layout not found {}: {}", layout.display(), e))?,
+            ),
+            None => None,
+        };
+
+        let mut changed = Vec::new();
+        for path in pages {
+            if let Some(filter) = &layout_filter {
+                let governing = self
+                    .resolve_layout_path(path)
+                    .and_then(|layout| layout.canonicalize().ok());
+                if governing.as_ref() != Some(filter) {
+                    continue;
+                }
+            }
+
+            let display_path = path
+                .strip_prefix(&self.src_dir)
+                .unwrap_or(path)
+                .display()
+                .to_string();
+            let page_html =
+                fs::read_to_string(path).map_err(|e| format!("{}: {}", display_path, e))?;
+            // `<include />` is not void in HTML, so close it explicitly before parsing.
+            let page_doc = parse_html().one(explicit_include_tags(&wrap_provider_contexts(
+                &page_html, None,
+            )));
+            let providers = self.extract_page_providers(&page_html, &page_doc, &HashMap::new());
+
+            let blocks = match self.apply_migration(op, providers)? {
+                Some(blocks) => blocks,
+                None => continue,
+            };
+
+            let migrated = join_provider_blocks(&blocks);
+            if migrated == comparable_source(&page_html) {
+                continue;
+            }
+
+            let final_text = restore_line_endings(&migrated, &page_html);
+            if dry_run {
+                print!(
+                    "{}",
+                    unified_diff(
+                        &page_html.replace("\r\n", "\n"),
+                        &final_text.replace("\r\n", "\n"),
+                        &display_path
+                    )
+                );
+            } else {
+                write_if_changed(path, &final_text)
+                    .map_err(|e| format!("{}: {}", display_path, e))?;
+                println!("[Migrate] Wrote {}", display_path);
+            }
+            changed.push(display_path);
+        }
+
+        println!(
+            "[Migrate] {} {} page(s).",
+            if dry_run { "Would change" } else { "Changed" },
+            changed.len()
+        );
+        Ok(changed)
+    }
+
+    /// Rewrites extracted providers for one page, returning `None` when `op` does not apply.
+    fn apply_migration(
+        &self,
+        op: &MigrationOp,
+        mut providers: ExtractedProviders,
+    ) -> Result<Option<Vec<String>>, String> {
+        let mut order = providers.order.clone();
+        let mut rendered: HashMap<String, String> = providers
+            .slots
+            .iter()
+            .map(|(name, slot)| (name.clone(), Self::render_extracted(slot)))
+            .collect();
+
+        match op {
+            MigrationOp::Rename { from, to } | MigrationOp::Merge { from, into: to }
+                if !providers.slots.contains_key(to) =>
+            {
+                let Some(slot) = providers.slots.get_mut(from) else {
+                    return Ok(None);
+                };
+                slot.attributes.insert("for-slot".to_string(), to.clone());
+                slot.original_html = slot
+                    .original_html
+                    .as_ref()
+                    .map(|html| rename_for_slot(html, to));
+                let html = Self::render_extracted(slot);
+                rendered.remove(from);
+                rendered.insert(to.clone(), html);
+                if let Some(leading) = providers.leading.remove(from) {
+                    providers.leading.insert(to.clone(), leading);
+                }
+                for name in order.iter_mut() {
+                    if name == from {
+                        *name = to.clone();
+                    }
+                }
+            }
+            MigrationOp::Rename { from, to } => {
+                if providers.slots.contains_key(from) {
+                    return Err(format!(
+                        "Cannot rename '{}' to '{}': the page already provides '{}'",
+                        from, to, to
+                    ));
+                }
+                return Ok(None);
+            }
+            MigrationOp::Merge { from, into } => {
+                if from == into {
+                    return Err(format!("Cannot merge '{}' into itself", from));
+                }
+                let Some(source) = providers.slots.get(from) else {
+                    return Ok(None);
+                };
+                let Some(target) = providers.slots.get(into) else {
+                    return Err(format!(
+                        "Cannot merge '{}' into '{}': the page does not provide '{}'",
+                        from, into, into
+                    ));
+                };
+                let mut inner_html = target.inner_html.clone();
+                if !source.inner_html.trim().is_empty() {
+                    inner_html.push_str(&source.inner_html);
+                }
+
+                // Source attributes carry over unless the target already sets them.
+                let mut attributes = target.attributes.clone();
+                let mut dropped = Vec::new();
+                for (name, value) in &source.attributes {
+                    if name == "for-slot" {
+                        continue;
+                    }
+                    match attributes.get(name) {
+                        None => {
+                            attributes.insert(name.clone(), value.clone());
+                        }
+                        Some(existing) if existing != value => dropped.push(name.as_str()),
+                        Some(_) => {}
+                    }
+                }
+                if !dropped.is_empty() {
+                    dropped.sort();
+                    println!(
+                        "[Warn] Dropped attribute(s) {} of '{}' merged into '{}'",
+                        dropped.join(", "),
+                        from,
+                        into
+                    );
+                }
+                rendered.insert(
+                    into.clone(),
+                    PageSlotContent::build_markup(
+                        &target.tag,
+                        &attributes,
+                        &inner_html,
+                        SlotClosingStyle::Explicit,
+                    ),
+                );
+                rendered.remove(from);
+                order.retain(|name| name != from);
+            }
+            MigrationOp::Split { slot, to, selector } => {
+                if providers.slots.contains_key(to) {
+                    return Err(format!(
+                        "Cannot split '{}' into '{}': the page already provides '{}'",
+                        slot, to, to
+                    ));
+                }
+                let Some(source) = providers.slots.get(slot) else {
+                    return Ok(None);
+                };
+
+                let fragment = parse_html().one(source.inner_html.clone());
+                let body = fragment
+                    .select_first("body")
+                    .map_err(|_| format!("Could not parse provider '{}'", slot))?;
+                let matches: Vec<NodeRef> = body
+                    .as_node()
+                    .select(selector)
+                    .map_err(|_| format!("Invalid selector: {}", selector))?
+                    .map(|element| element.as_node().clone())
+                    .collect();
+                if matches.is_empty() {
+                    return Ok(None);
+                }
+
+                let mut moved = String::new();
+                for node in &matches {
+                    moved.push_str(&self.get_outer_html(node));
+                    if let Some(previous) = node.previous_sibling() {
+                        let is_blank = previous
+                            .as_text()
+                            .map(|text| text.borrow().trim().is_empty())
+                            .unwrap_or(false);
+                        if is_blank {
+                            previous.detach();
+                        }
+                    }
+                    node.detach();
+                }
+                let remaining = self.get_inner_html(body.as_node());
+
+                rendered.insert(
+                    slot.clone(),
+                    PageSlotContent::build_markup(
+                        &source.tag,
+                        &source.attributes,
+                        &remaining,
+                        SlotClosingStyle::Explicit,
+                    ),
+                );
+                let mut new_attributes = HashMap::new();
+                new_attributes.insert("for-slot".to_string(), to.clone());
+                rendered.insert(
+                    to.clone(),
+                    PageSlotContent::build_markup(
+                        &source.tag,
+                        &new_attributes,
+                        &moved,
+                        SlotClosingStyle::Explicit,
+                    ),
+                );
+                let index = order.iter().position(|name| name == slot).unwrap();
+                order.insert(index + 1, to.clone());
+            }
+            MigrationOp::Delete { slot } => {
+                if rendered.remove(slot).is_none() {
+                    return Ok(None);
+                }
+                order.retain(|name| name != slot);
+            }
+            MigrationOp::Retag { slot, tag } => {
+                let Some(source) = providers.slots.get(slot) else {
+                    return Ok(None);
+                };
+                let closing_style = if is_void_element(tag) {
+                    SlotClosingStyle::Void
+                } else {
+                    SlotClosingStyle::Explicit
+                };
+                rendered.insert(
+                    slot.clone(),
+                    PageSlotContent::build_markup(
+                        tag,
+                        &source.attributes,
+                        &source.inner_html,
+                        closing_style,
+                    ),
+                );
+            }
+        }
+
+        let mut blocks: Vec<String> = order
+            .iter()
+            .filter_map(|name| {
+                let html = rendered.remove(name)?;
+                let leading = providers.leading.get(name).map(String::as_str);
+                Some(format!("{}{}", leading.unwrap_or(""), html))
+            })
+            .collect();
+        if !providers.trailing.is_empty() {
+            blocks.push(providers.trailing);
+        }
+        Ok(Some(blocks))
+    }
+
+    fn render_extracted(slot: &ExtractedPageSlot) -> String {
+        PageSlotContent {
+            tag: slot.tag.clone(),
+            inner_html: slot.inner_html.clone(),
+            attributes: slot.attributes.clone(),
+            original_html: slot.original_html.clone(),
+            closing_style: slot.closing_style,
+        }
+        .render()
+    }
+
+    /// Resolves layouts and expands includes for `pages` without writing output,
+    /// filling the dependency graph.
+    fn collect_dependencies(&self, pages: &[PathBuf]) -> bool {
+        let mut ok = true;
+        for path in pages {
+            let display_path = path
+                .strip_prefix(&self.src_dir)
+                .unwrap_or(path)
+                .display()
+                .to_string();
+            let page_key = path.canonicalize().unwrap_or_else(|_| path.clone());
+            let result = self.layout_for_page(path).and_then(|_| {
+                let page_html = fs::read_to_string(path).map_err(|e| e.to_string())?;
+                self.expand_includes_in_html(
+                    &page_html,
+                    path.parent().unwrap_or(self.src_dir.as_path()),
+                    &mut vec![page_key.clone()],
+                )
+            });
+            for line in self.take_warnings(&page_key) {
+                println!("{}", line);
+            }
+            if let Err(e) = result {
+                eprintln!("[Error] {}: {}", display_path, e);
+                ok = false;
+            }
+        }
+        ok
+    }
+
+    /// Walks the source folder once and sorts files into pages, other HTML and assets.
+    fn scan_source_files(&self) -> SourceFiles {
+        let mut files = SourceFiles::default();
+        for entry in WalkDir::new(&self.src_dir)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+        {
+            self.classify_source(entry.into_path(), &mut files);
+        }
+
+        files.pages.sort();
+        files.fragments.sort();
+        files.passthrough.sort();
+        files.assets.sort();
+        self.separate_output_collisions(&mut files);
+        files
+    }
+
+    /// Moves passthrough files whose output path a page or an earlier passthrough
+    /// file already writes into `files.collisions`, so the build keeps the first
+    /// source's output and fails with an error naming both.
+    fn separate_output_collisions(&self, files: &mut SourceFiles) {
+        files
+            .passthrough
+            .extend(files.collisions.drain(..).map(|(path, _)| path));
+        files.passthrough.sort();
+
+        let mut owners: HashMap<PathBuf, PathBuf> = HashMap::new();
+        for page in &files.pages {
+            if let Ok(rel) = page.strip_prefix(&self.src_dir) {
+                owners.insert(output_rel_path(rel), page.clone());
+            }
+        }
+        for path in std::mem::take(&mut files.passthrough) {
+            let Ok(rel) = path.strip_prefix(&self.src_dir) else {
+                files.passthrough.push(path);
+                continue;
+            };
+            let out_rel = output_rel_path(rel);
+            match owners.get(&out_rel) {
+                Some(owner) => {
+                    let error = format!(
+                        "[Error] {} and {} are both written to {}",
+                        self.source_label(owner),
+                        self.source_label(&path),
+                        out_rel.to_string_lossy().replace('\\', "/")
+                    );
+                    files.collisions.push((path, error));
+                }
+                None => {
+                    owners.insert(out_rel, path.clone());
+                    files.passthrough.push(path);
+                }
+            }
+        }
+    }
+
+    /// Adds one source file to the matching list of `files`, with a warning when an
+    /// explicit rule disagrees with its `for-slot` content.
+    fn classify_source(&self, path: PathBuf, files: &mut SourceFiles) {
+        if !self.is_html_file(&path) {
+            files.assets.push(path);
+            return;
+        }
+        if self.is_passthrough_html(&path) {
+            files.passthrough.push(path);
+            return;
+        }
+        // Same decision as `is_page_html`, without matching the globs twice.
+        let is_page = match self.page_rule(&path) {
+            Some(rule) => {
+                if rule.is_page() != self.html_has_slot_providers(&path) {
+                    let warning = format!(
+                        "[Warn] {} {} for-slot providers but is a {} ({})",
+                        self.source_label(&path),
+                        if rule.is_page() { "has no" } else { "has" },
+                        if rule.is_page() { "page" } else { "partial" },
+                        rule.describe()
+                    );
+                    files.warnings.push((path.clone(), warning));
+                }
+                rule.is_page()
+            }
+            None => self.html_has_slot_providers(&path),
+        };
+        if is_page && !self.is_layout_file(&path) {
+            files.pages.push(path);
+        } else {
+            files.fragments.push(path);
+        }
+    }
+
+    /// The previous build's scan with only `paths` classified again. `None` when
+    /// there is no previous scan or a path was added or removed, which needs a new walk.
+    fn rescan_changed(&self, paths: &HashSet<PathBuf>) -> Option<SourceFiles> {
+        let mut files = self.state.lock().unwrap().sources.clone()?;
+        let known: HashSet<PathBuf> = files.all_files().into_iter().collect();
+        if paths
+            .iter()
+            .any(|path| !known.contains(path) || !path.is_file())
+        {
+            return None;
+        }
+
+        for path in paths {
+            for list in [
+                &mut files.pages,
+                &mut files.fragments,
+                &mut files.passthrough,
+                &mut files.assets,
+            ] {
+                list.retain(|file| file != path);
+            }
+            files.warnings.retain(|(file, _)| file != path);
+            files.collisions.retain(|(file, _)| file != path);
+            self.classify_source(path.clone(), &mut files);
+        }
+
+        files.pages.sort();
+        files.fragments.sort();
+        files.passthrough.sort();
+        files.assets.sort();
+        files.warnings.sort();
+        self.separate_output_collisions(&mut files);
+        Some(files)
+    }
+
+    /// Holds `line` for the report of the page (or layout) expanded from `root`, or
+    /// prints it at once outside a page build.
+    fn defer_warning(&self, root: Option<&PathBuf>, line: String) {
+        match root {
+            Some(root) => self
+                .state
+                .lock()
+                .unwrap()
+                .deferred_warnings
+                .entry(root.clone())
+                .or_default()
+                .push(line),
+            None => println!("{}", line),
+        }
+    }
+
+    /// Removes and returns the warnings deferred for `root`.
+    fn take_warnings(&self, root: &Path) -> Vec<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .deferred_warnings
+            .remove(root)
+            .unwrap_or_default()
+    }
+
+    fn layout_for_page(&self, page_path: &Path) -> Result<Arc<LayoutData>, String> {
+        let layout_path = self.resolve_layout_path(page_path).ok_or_else(|| {
+            let rel = page_path
+                .strip_prefix(&self.src_dir)
+                .unwrap_or(page_path)
+                .display()
+                .to_string();
+            format!("Missing _layout.html for {}", rel)
+        })?;
+
+        let cache_key = layout_path
+            .canonicalize()
+            .unwrap_or_else(|_| layout_path.clone());
+        let page_key = page_path
+            .canonicalize()
+            .unwrap_or_else(|_| page_path.to_path_buf());
+        self.state
+            .lock()
+            .unwrap()
+            .deps
+            .page_layouts
+            .insert(page_key.clone(), cache_key.clone());
+        if let Some(layout) = self.state.lock().unwrap().layouts.get(&cache_key) {
+            return Ok(Arc::clone(layout));
+        }
+
+        let loaded = self.load_layout_data(&layout_path);
+        // Warnings from loading the layout belong to the page that needed it.
+        for line in self.take_warnings(&cache_key) {
+            self.defer_warning(Some(&page_key), line);
+        }
+        let layout = Arc::new(loaded?);
+        self.state
+            .lock()
+            .unwrap()
+            .layouts
+            .insert(cache_key, Arc::clone(&layout));
+        Ok(layout)
+    }
+
+    fn resolve_layout_path(&self, page_path: &Path) -> Option<PathBuf> {
+        let mut current = page_path.parent()?;
+
+        loop {
+            if !current.starts_with(&self.src_dir) {
+                return None;
+            }
+
+            let candidate = current.join("_layout.html");
+            if candidate.exists() {
+                return Some(candidate);
+            }
+
+            if current == self.src_dir {
+                return None;
+            }
+
+            current = current.parent()?;
+        }
+    }
+
+    fn load_layout_data(&self, layout_path: &Path) -> Result<LayoutData, String> {
+        let layout_html = self.expand_includes_in_file(layout_path)?;
+        let slots = collect_slot_specs(&layout_html);
+
+        if slots.is_empty() {
+            let rel = layout_path
+                .strip_prefix(&self.src_dir)
+                .unwrap_or(layout_path)
+                .display()
+                .to_string();
+            let key = layout_path
+                .canonicalize()
+                .unwrap_or_else(|_| layout_path.to_path_buf());
+            self.defer_warning(
+                Some(&key),
+                format!("[Warn] No slots in {}. Nothing to merge.", rel),
+            );
+        }
+
+        let layout_names: HashSet<String> = slots.iter().map(|slot| slot.name.clone()).collect();
+        let mut slot_aliases = HashMap::new();
+        for slot in &slots {
+            for alias in &slot.aliases {
+                if layout_names.contains(alias) {
+                    return Err(format!(
+                        "Slot alias '{}' on '{}' collides with an existing slot",
+                        alias, slot.name
+                    ));
+                }
+                if let Some(previous) = slot_aliases.insert(alias.clone(), slot.name.clone()) {
+                    return Err(format!(
+                        "Slot alias '{}' is declared by both '{}' and '{}'",
+                        alias, previous, slot.name
+                    ));
+                }
+            }
+        }
+
+        let mut template = compile_template(&layout_html, &slots);
+        clear_attr_slot_defaults(&mut template);
+        Ok(LayoutData {
+            template,
+            slots,
+            layout_names,
+            slot_aliases,
+        })
+    }
+
+    fn is_layout_file(&self, path: &Path) -> bool {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| name.eq_ignore_ascii_case("_layout.html"))
+            .unwrap_or(false)
+    }
+
+    fn normalize_watch_path(&self, path: &Path, src_dir_canonical: &Path) -> Option<PathBuf> {
+        let mut candidate = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            self.src_dir.join(path)
+        };
+
+        if let Ok(canonical) = candidate.canonicalize() {
+            candidate = canonical;
+        }
+
+        if !candidate.exists() {
+            return None;
+        }
+
+        if !candidate.starts_with(src_dir_canonical) {
+            return None;
+        }
+
+        if self.is_layout_file(&candidate) {
+            return None;
+        }
+
+        if !self.is_page_html(&candidate) {
+            return None;
+        }
+
+        Some(candidate)
+    }
+
+    fn is_html_file(&self, path: &Path) -> bool {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.eq_ignore_ascii_case("html"))
+            .unwrap_or(false)
+    }
+
+    fn is_page_html(&self, path: &Path) -> bool {
+        if !self.is_html_file(path) {
+            return false;
+        }
+
+        if self.is_layout_file(path) || self.is_passthrough_html(path) {
+            return false;
+        }
+
+        match self.page_rule(path) {
+            Some(rule) => rule.is_page(),
+            None => self.html_has_slot_providers(path),
+        }
+    }
+
+    fn is_component_html(&self, path: &Path) -> bool {
+        self.is_html_file(path)
+            && !self.is_layout_file(path)
+            && !self.is_passthrough_html(path)
+            && !self.is_page_html(path)
+    }
+
+    /// Standalone HTML shipped as is: `*.static.html` files (written without the
+    /// `.static` part) and files matching a `--passthrough` glob.
+    fn is_passthrough_html(&self, path: &Path) -> bool {
+        if !self.is_html_file(path) || self.is_layout_file(path) {
+            return false;
+        }
+        if static_html_stem(path).is_some() {
+            return true;
+        }
+        let label = self.source_label(path);
+        self.options
+            .passthrough_globs
+            .iter()
+            .any(|glob| glob.matches(&label))
+    }
+
+    /// Copies a passthrough file to the output byte for byte, or expands its includes
+    /// first when `--passthrough-includes` is set (which needs UTF-8 text).
+    fn build_passthrough(&self, path: &Path) -> PageReport {
+        let mut report = PageReport {
+            ok: true,
+            ..PageReport::default()
+        };
+        let Ok(rel_path) = path.strip_prefix(&self.src_dir) else {
+            return report;
+        };
+        let out_rel = output_rel_path(rel_path);
+
+        let mut contents = match fs::read(path) {
+            Ok(content) => content,
+            Err(e) => {
+                report.error(format!("[Error] {}: {}", rel_path.display(), e));
+                report.ok = false;
+                return report;
+            }
+        };
+        if self.options.passthrough_includes {
+            let key = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+            self.state.lock().unwrap().deps.reset(&key);
+            let expanded = String::from_utf8(contents)
+                .map_err(|e| e.to_string())
+                .and_then(|html| {
+                    self.expand_includes_in_html(
+                        &html,
+                        path.parent().unwrap_or(self.src_dir.as_path()),
+                        &mut vec![key.clone()],
+                    )
+                });
+            for line in self.take_warnings(&key) {
+                report.info(line);
+            }
+            match expanded {
+                Ok(expanded) => contents = expanded.into_bytes(),
+                Err(e) => {
+                    report.error(format!("[Error] {}: {}", rel_path.display(), e));
+                    report.ok = false;
+                    return report;
+                }
+            }
+        }
+
+        let dest = self.out_dir.join(&out_rel);
+        let _ = fs::create_dir_all(dest.parent().unwrap());
+        match write_if_changed(&dest, &contents) {
+            Ok(true) => report.info(format!("📄 Passed through {}", out_rel.display())),
+            Ok(false) => {}
+            Err(e) => {
+                report.error(format!("[Error] {}", e));
+                report.ok = false;
+                return report;
+            }
+        }
+        report.output = Some((out_rel, Sha256::digest(&contents).to_vec()));
+        report
+    }
+
+    /// The explicit rule that decides whether `path` is a page: `--partials` and
+    /// `--pages` globs first, then an underscore-prefixed file or folder name or a
+    /// `components/` folder. `None` leaves it to the `for-slot` heuristic.
+    fn page_rule(&self, path: &Path) -> Option<PageRule> {
+        let label = self.source_label(path);
+        let matching = |globs: &[SourceGlob]| {
+            globs
+                .iter()
+                .find(|glob| glob.matches(&label))
+                .map(|glob| glob.pattern.clone())
+        };
+
+        if let Some(pattern) = matching(&self.options.partial_globs) {
+            return Some(PageRule::PartialGlob(pattern));
+        }
+        if let Some(pattern) = matching(&self.options.page_globs) {
+            return Some(PageRule::PageGlob(pattern));
+        }
+
+        let segments: Vec<&str> = label.split('/').collect();
+        if segments.iter().any(|segment| segment.starts_with('_')) {
+            return Some(PageRule::Underscore);
+        }
+        if segments[..segments.len() - 1]
+            .iter()
+            .any(|segment| segment.eq_ignore_ascii_case("components"))
+        {
+            return Some(PageRule::ComponentsFolder);
+        }
+        None
+    }
+
+    /// Prints the page/partial warnings of a scan and keeps the scan for the next
+    /// watch build. Watch builds repeat a warning only once it has gone away.
+    fn print_scan_warnings(&self, sources: &SourceFiles) {
+        let mut state = self.state.lock().unwrap();
+        for (_, warning) in &sources.warnings {
+            if !state.reported_warnings.contains(warning) {
+                println!("{}", warning);
+            }
+        }
+        state.reported_warnings = sources
+            .warnings
+            .iter()
+            .map(|(_, warning)| warning.clone())
+            .collect();
+        state.sources = Some(sources.clone());
+    }
+
+    /// Prints the unused component and asset report; returns false when strict
+    /// mode is on and something is unused.
+    fn report_unused(&self, sources: &SourceFiles) -> bool {
+        let (components, assets) = self.find_unused(sources);
+        for component in &components {
+            println!("[Unused] Component {}", component.display());
+        }
+        for asset in &assets {
+            println!("[Unused] Asset {}", asset.display());
+        }
+
+        let total = components.len() + assets.len();
+        if total == 0 {
+            println!("[Unused] Every component and asset is used.");
+            return true;
+        }
+        if self.options.strict_unused {
+            println!(
+                "[Error] {} unused component(s) and {} unused asset(s)",
+                components.len(),
+                assets.len()
+            );
+            return false;
+        }
+        true
+    }
+
+    /// Returns source-relative paths of component HTML files that no page or layout
+    /// includes, and of assets that no built page or stylesheet references.
+    /// Relies on a dependency graph that covers every page, as kept across watch
+    /// builds and restored from the build cache.
+    fn find_unused(&self, sources: &SourceFiles) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        let state = self.state.lock().unwrap();
+        let mut included: HashSet<PathBuf> = HashSet::new();
+        for (page, layout) in &state.deps.page_layouts {
+            included.extend(state.deps.transitive_includes(page));
+            included.extend(state.deps.transitive_includes(layout));
+        }
+        for file in &sources.passthrough {
+            let key = file.canonicalize().unwrap_or_else(|_| file.to_path_buf());
+            included.extend(state.deps.transitive_includes(&key));
+        }
+        let inlined = state.inlined_assets.clone();
+        drop(state);
+
+        let pages: Vec<PathBuf> = sources
+            .pages
+            .iter()
+            .chain(&sources.passthrough)
+            .cloned()
+            .collect();
+        let referenced = self.referenced_assets(&pages, &sources.assets);
+
+        let mut components = Vec::new();
+        let mut assets = Vec::new();
+        for path in &sources.fragments {
+            let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+            if self.is_component_html(path) && !included.contains(&canonical) {
+                if let Ok(rel) = path.strip_prefix(&self.src_dir) {
+                    components.push(rel.to_path_buf());
+                }
+            }
+        }
+        for path in &sources.assets {
+            let Ok(rel) = path.strip_prefix(&self.src_dir) else {
+                continue;
+            };
+            let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+            assets.push((rel.to_path_buf(), canonical));
+        }
+
+        let mut unused_assets: Vec<PathBuf> = assets
+            .into_iter()
+            .filter(|(rel, canonical)| !referenced.contains(rel) && !inlined.contains(canonical))
+            .map(|(rel, _)| rel)
+            .collect();
+        components.sort();
+        unused_assets.sort();
+        (components, unused_assets)
+    }
+
+    /// Source-relative paths of the assets referenced from the built output of
+    /// `pages` and from the stylesheets among `assets` (through `url()` and `@import`).
+    fn referenced_assets(&self, pages: &[PathBuf], assets: &[PathBuf]) -> HashSet<PathBuf> {
+        let mut referenced = HashSet::new();
+        let mut add_references = |rel: &Path, text: &str| {
+            let base = rel.parent().unwrap_or(Path::new(""));
+            referenced.extend(
+                asset_references(text)
+                    .iter()
+                    .filter_map(|reference| resolve_asset_reference(base, reference)),
+            );
+        };
+
+        for page in pages {
+            let Ok(rel) = page.strip_prefix(&self.src_dir) else {
+                continue;
+            };
+            if let Ok(html) = fs::read_to_string(self.out_dir.join(output_rel_path(rel))) {
+                add_references(rel, &html);
+            }
+        }
+        for asset in assets {
+            let is_css = asset
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .map(|ext| ext.eq_ignore_ascii_case("css"))
+                .unwrap_or(false);
+            let Ok(rel) = asset.strip_prefix(&self.src_dir) else {
+                continue;
+            };
+            if is_css {
+                if let Ok(css) = fs::read_to_string(asset) {
+                    add_references(rel, &css);
+                }
+            }
+        }
+        referenced
+    }
+
+    /// Recomputes which inlined assets need no output copy. A full build checks every
+    /// page and stylesheet; an incremental build keeps the previous answer except for
+    /// assets that `pages` or the changed `assets` now reference, or that are no longer
+    /// inlined.
+    fn update_inline_only(&self, pages: &[PathBuf], assets: &[PathBuf], full_rebuild: bool) {
+        let src_dir_canonical = self
+            .src_dir
+            .canonicalize()
+            .unwrap_or_else(|_| self.src_dir.clone());
+        let referenced = self.referenced_assets(pages, assets);
+
+        let mut state = self.state.lock().unwrap();
+        let BuildState {
+            inlined_assets,
+            inline_only,
+            ..
+        } = &mut *state;
+        if full_rebuild {
+            *inline_only = inlined_assets.clone();
+        }
+        inline_only.retain(|asset| {
+            inlined_assets.contains(asset)
+                && asset
+                    .strip_prefix(&src_dir_canonical)
+                    .map(|rel| !referenced.contains(rel))
+                    .unwrap_or(true)
+        });
+    }
+
+    fn path_missing_with_retry(&self, path: &Path) -> bool {
+        if path.exists() {
+            return false;
+        }
+        for _ in 0..3 {
+            thread::sleep(Duration::from_millis(10));
+            if path.exists() {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn get_inner_html(&self, node: &NodeRef) -> String {
+        // Get the inner HTML by serializing all children
+        let mut result = Vec::new();
+        for child in node.children() {
+            let mut child_html = Vec::new();
+            child.serialize(&mut child_html).ok();
+            result.extend(child_html);
+        }
+        String::from_utf8_lossy(&result).to_string()
+    }
+
+    fn get_outer_html(&self, node: &NodeRef) -> String {
+        let mut result = Vec::new();
+        node.serialize(&mut result).ok();
+        String::from_utf8_lossy(&result).to_string()
+    }
+
+    /// Classifies an HTML file with a text scan, reusing the previous answer while the
+    /// file's modification time and size are unchanged.
+    fn html_has_slot_providers(&self, path: &Path) -> bool {
+        let stamp = fs::metadata(path)
+            .ok()
+            .and_then(|meta| Some((meta.modified().ok()?, meta.len())));
+        if let Some((modified, len)) = stamp {
+            let state = self.state.lock().unwrap();
+            if let Some(&(cached_modified, cached_len, is_page)) = state.html_kinds.get(path) {
+                if cached_modified == modified && cached_len == len {
+                    return is_page;
+                }
+            }
+        }
+
+        let is_page = fs::read_to_string(path)
+            .map(|html| has_slot_providers(&html))
+            .unwrap_or(false);
+        if let Some((modified, len)) = stamp {
+            self.state
+                .lock()
+                .unwrap()
+                .html_kinds
+                .insert(path.to_path_buf(), (modified, len, is_page));
+        }
+        is_page
+    }
+
+    fn expand_includes_in_file(&self, path: &Path) -> Result<String, String> {
+        self.expand_includes_from_file(path, &mut Vec::new())
+    }
+
+    fn expand_includes_from_file(
+        &self,
+        path: &Path,
+        stack: &mut Vec<PathBuf>,
+    ) -> Result<String, String> {
+        let canonical = path
+            .canonicalize()
+            .map_err(|e| format!("Failed to read include {}: {}", path.display(), e))?;
+
+        if let Some(index) = stack.iter().position(|entry| *entry == canonical) {
+            let mut chain = stack[index..]
+                .iter()
+                .map(|entry| entry.display().to_string())
+                .collect::<Vec<_>>();
+            chain.push(canonical.display().to_string());
+            return Err(format!("Include cycle detected: {}", chain.join(" -> ")));
+        }
+
+        let key = (
+            canonical.clone(),
+            self.is_layout_file(stack.first().unwrap_or(&canonical)),
+        );
+        let cached = self.state.lock().unwrap().include_cache.get(&key).cloned();
+        if let Some(expanded) = cached {
+            return Ok(expanded);
+        }
+
+        let html = fs::read_to_string(&canonical)
+            .map_err(|e| format!("Failed to read include {}: {}", canonical.display(), e))?;
+
+        self.state.lock().unwrap().deps.reset(&canonical);
+        stack.push(canonical.clone());
+        let expanded = self.expand_includes_in_html(
+            &html,
+            canonical.parent().unwrap_or(self.src_dir.as_path()),
+            stack,
+        );
+        stack.pop();
+
+        let expanded = expanded?;
+        self.state
+            .lock()
+            .unwrap()
+            .include_cache
+            .insert(key, expanded.clone());
+        Ok(expanded)
+    }
+
+    fn expand_includes_in_html(
+        &self,
+        html: &str,
+        current_dir: &Path,
+        stack: &mut Vec<PathBuf>,
+    ) -> Result<String, String> {
+        let mut result = String::with_capacity(html.len());
+        let mut last_end = 0;
+
+        for tag in find_include_tags(html) {
+            result.push_str(&html[last_end..tag.start]);
+
+            if is_glob_pattern(&tag.src) {
+                let mut paths = self.resolve_include_glob(&tag.src, current_dir, stack)?;
+                if tag.has_attribute("reverse") {
+                    paths.reverse();
+                }
+                if let Some(limit) = tag.attribute("limit") {
+                    let limit: usize = limit.trim().parse().map_err(|_| {
+                        format!("Invalid limit '{}' for include {}", limit, tag.src)
+                    })?;
+                    paths.truncate(limit);
+                }
+                for path in paths {
+                    result.push_str(&self.expand_include_tag(&tag, &path, current_dir, stack)?);
+                }
+            } else {
+                let include_path =
+                    self.resolve_include_path(&tag.src, current_dir, stack.last())?;
+                result.push_str(&self.expand_include_tag(
+                    &tag,
+                    &include_path,
+                    current_dir,
+                    stack,
+                )?);
+            }
+
+            last_end = tag.end;
+        }
+
+        result.push_str(&html[last_end..]);
+        Ok(result)
+    }
+
+    fn expand_include_tag(
+        &self,
+        tag: &IncludeTag,
+        include_path: &Path,
+        current_dir: &Path,
+        stack: &mut Vec<PathBuf>,
+    ) -> Result<String, String> {
+        if let Some(includer) = stack.last() {
+            let included = include_path
+                .canonicalize()
+                .unwrap_or_else(|_| include_path.to_path_buf());
+            self.state
+                .lock()
+                .unwrap()
+                .deps
+                .record_include(includer, &included);
+        }
+
+        let kind = IncludeKind::from_path(include_path);
+        if kind != IncludeKind::Html {
+            if !tag.params().is_empty()
+                || tag.inner.is_some()
+                || tag.has_attribute("select")
+                || tag.has_attribute("from-slot")
+            {
+                return Err(format!(
+                    "Parameters, child content and fragments are only supported for HTML includes: {}",
+                    tag.src
+                ));
+            }
+            return self.inline_asset(include_path, kind, tag.has_attribute("wrap"));
+        }
+
+        let mut expanded = self.expand_includes_from_file(include_path, stack)?;
+        if let Some(selector) = tag.attribute("select") {
+            expanded = self.select_fragment(&expanded, selector, &tag.src)?;
+        } else if let Some(slot_name) = tag.attribute("from-slot") {
+            expanded = self.slot_provider_fragment(&expanded, slot_name, &tag.src)?;
+        }
+        let mut args = tag.params();
+        if let Some(inner) = tag.inner {
+            let inner = self.expand_includes_in_html(inner, current_dir, stack)?;
+            args.extend(self.component_child_args(&inner));
+        }
+
+        // Components spliced into a layout without parameters keep their `slot`
+        // elements, which then declare layout slots.
+        let in_layout = stack
+            .first()
+            .map(|root| self.is_layout_file(root))
+            .unwrap_or(false);
+        if !args.is_empty() || !in_layout {
+            expanded = self.apply_component_params(&expanded, &args, &tag.src)?;
+        }
+        if self.options.provenance {
+            let label = self.source_label(include_path);
+            expanded =
+                format!("<!-- begin include {label} -->{expanded}<!-- end include {label} -->");
+        }
+        Ok(expanded)
+    }
+
+    /// Source-relative path with forward slashes, for messages and debug comments.
+    fn source_label(&self, path: &Path) -> String {
+        let rel = match path.strip_prefix(&self.src_dir) {
+            Ok(rel) => Some(rel.to_path_buf()),
+            Err(_) => self.src_dir.canonicalize().ok().and_then(|src| {
+                let canonical = path.canonicalize().ok()?;
+                canonical.strip_prefix(&src).ok().map(Path::to_path_buf)
+            }),
+        };
+        rel.as_deref()
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+
+    /// Expands a `*`/`?`/`**` include pattern into sorted file paths, using the first
+    /// search location (same order as `resolve_include_path`) that has any match.
+    fn resolve_include_glob(
+        &self,
+        pattern: &str,
+        current_dir: &Path,
+        stack: &[PathBuf],
+    ) -> Result<Vec<PathBuf>, String> {
+        let includer = stack.last();
+        let bases: Vec<PathBuf> = if pattern.starts_with('/') {
+            vec![self.src_dir.clone()]
+        } else {
+            std::iter::once(current_dir.to_path_buf())
+                .chain(
+                    self.options
+                        .include_roots
+                        .iter()
+                        .map(|root| self.src_dir.join(root)),
+                )
+                .collect()
+        };
+        let pattern = pattern.trim_start_matches('/');
+
+        // Walk only from the longest directory prefix without wildcards.
+        let segments: Vec<&str> = pattern.split('/').collect();
+        let fixed = segments
+            .iter()
+            .take_while(|segment| !is_glob_pattern(segment))
+            .count()
+            .min(segments.len() - 1);
+        let fixed_prefix = segments[..fixed].join("/");
+        let matcher = glob_regex(&segments[fixed..].join("/"))
+            .ok_or_else(|| format!("Invalid include pattern: {}", pattern))?;
+
+        for base in bases {
+            let walk_root = base.join(&fixed_prefix);
+            if let Some(includer) = includer {
+                let watched = walk_root
+                    .canonicalize()
+                    .unwrap_or_else(|_| walk_root.clone());
+                self.state
+                    .lock()
+                    .unwrap()
+                    .deps
+                    .record_include(includer, &watched);
+            }
+            if !walk_root.is_dir() {
+                continue;
+            }
+
+            let mut matches: Vec<PathBuf> = WalkDir::new(&walk_root)
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.path().is_file())
+                .filter(|e| {
+                    e.path()
+                        .strip_prefix(&walk_root)
+                        .ok()
+                        .and_then(|rel| rel.to_str())
+                        .map(|rel| matcher.is_match(&rel.replace('\\', "/")))
+                        .unwrap_or(false)
+                })
+                .map(|e| e.path().to_path_buf())
+                .collect();
+            if !matches.is_empty() {
+                matches.sort();
+                return Ok(matches);
+            }
+        }
+
+        self.defer_warning(
+            stack.first(),
+            format!("[Warn] Include pattern {} matched no files", pattern),
+        );
+        Ok(Vec::new())
+    }
+
+    /// Keeps only the elements of an included document that match `selector`, with
+    /// `for-slot` markers removed so a selected page provider is not read as a slot.
+    fn select_fragment(&self, html: &str, selector: &str, src: &str) -> Result<String, String> {
+        let doc = parse_html().one(html);
+        let matches: Vec<NodeRef> = doc
+            .select(selector)
+            .map_err(|_| format!("Invalid selector '{}' for include {}", selector, src))?
+            .map(|element| element.as_node().clone())
+            .collect();
+        if matches.is_empty() {
+            return Err(format!(
+                "Selector '{}' matched nothing in include {}",
+                selector, src
+            ));
+        }
+
+        let mut fragment = String::new();
+        for node in matches {
+            for descendant in node.inclusive_descendants() {
+                if let Some(element) = descendant.as_element() {
+                    element.attributes.borrow_mut().remove("for-slot");
+                }
+            }
+            fragment.push_str(&self.get_outer_html(&node));
+        }
+        Ok(fragment)
+    }
+
+    /// Returns the inner HTML of the first `for-slot="slot_name"` provider in an included page.
+    fn slot_provider_fragment(
+        &self,
+        html: &str,
+        slot_name: &str,
+        src: &str,
+    ) -> Result<String, String> {
+        let doc = parse_html().one(explicit_include_tags(&wrap_provider_contexts(html, None)));
+        doc.select("[for-slot]")
+            .unwrap()
+            .find(|element| element.attributes.borrow().get("for-slot") == Some(slot_name))
+            .map(|element| self.get_inner_html(element.as_node()))
+            .ok_or_else(|| format!("Include {} has no provider for slot '{}'", src, slot_name))
+    }
+
+    /// Reads a non-HTML include for inlining and records it so `copy_assets_diff` can
+    /// skip files that are only used inline.
+    fn inline_asset(&self, path: &Path, kind: IncludeKind, wrap: bool) -> Result<String, String> {
+        let canonical = path
+            .canonicalize()
+            .map_err(|e| format!("Failed to read include {}: {}", path.display(), e))?;
+        let content = fs::read_to_string(&canonical)
+            .map_err(|e| format!("Failed to read include {}: {}", canonical.display(), e))?;
+        self.state.lock().unwrap().inlined_assets.insert(canonical);
+
+        Ok(match kind {
+            IncludeKind::Svg => strip_xml_prologue(&content),
+            IncludeKind::Css if wrap => format!("<style>{}</style>", content.trim_end()),
+            IncludeKind::Js if wrap => format!("<script>{}</script>", content.trim_end()),
+            IncludeKind::Css | IncludeKind::Js | IncludeKind::Html => content,
+            IncludeKind::Text => escape_html_text(&content),
+        })
+    }
+
+    /// Resolves an include `src`: `/`-prefixed paths are relative to the source root,
+    /// other paths are tried next to the including file and then in each include root.
+    fn resolve_include_path(
+        &self,
+        src: &str,
+        current_dir: &Path,
+        includer: Option<&PathBuf>,
+    ) -> Result<PathBuf, String> {
+        let candidates: Vec<PathBuf> = if let Some(rooted) = src.strip_prefix('/') {
+            vec![self.src_dir.join(rooted)]
+        } else {
+            std::iter::once(current_dir.join(src))
+                .chain(
+                    self.options
+                        .include_roots
+                        .iter()
+                        .map(|root| self.src_dir.join(root).join(src)),
+                )
+                .collect()
+        };
+
+        if let Some(found) = candidates.iter().find(|candidate| candidate.is_file()) {
+            return Ok(found.clone());
+        }
+
+        // Depend on every location tried, so creating the file rebuilds the includer.
+        if let Some(includer) = includer {
+            let mut state = self.state.lock().unwrap();
+            for candidate in &candidates {
+                state.deps.record_include(includer, candidate);
+            }
+        }
+
+        let tried = candidates
+            .iter()
+            .map(|candidate| {
+                candidate
+                    .strip_prefix(&self.src_dir)
+                    .unwrap_or(candidate)
+                    .display()
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        Err(format!(
+            "Include not found: {} (tried {})",
+            src,
+            tried.join(", ")
+        ))
+    }
+
+    /// Fills the `slot` placeholders of an expanded component from include attributes.
+    /// Includes without parameters are spliced unchanged, so components used inside
+    /// layouts can still carry layout slots.
+    fn apply_component_params(
+        &self,
+        component_html: &str,
+        params: &[(String, ComponentArg)],
+        src: &str,
+    ) -> Result<String, String> {
+        if params.is_empty() && slot_attribute_spans(component_html, "slot").is_empty() {
+            return Ok(component_html.to_string());
+        }
+        let slots = collect_slot_specs(component_html);
+
+        let mut seen = HashSet::new();
+        let repeated: Vec<&str> = params
+            .iter()
+            .filter(|(name, _)| !seen.insert(name.as_str()))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        if !repeated.is_empty() {
+            return Err(format!(
+                "Parameters given more than once for include {}: {}",
+                src,
+                repeated.join(", ")
+            ));
+        }
+
+        let unknown: Vec<&str> = params
+            .iter()
+            .filter(|(name, _)| !slots.iter().any(|slot| slot.name == *name))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        if !unknown.is_empty() {
+            return Err(format!(
+                "Unknown parameters for include {}: {}",
+                src,
+                unknown.join(", ")
+            ));
+        }
+
+        let missing: Vec<&str> = slots
+            .iter()
+            .filter(|slot| !slot.optional && !params.iter().any(|(name, _)| *name == slot.name))
+            .map(|slot| slot.name.as_str())
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!(
+                "Missing required parameters for include {}: {}",
+                src,
+                missing.join(", ")
+            ));
+        }
+
+        let mut fills = HashMap::new();
+        for slot in &slots {
+            let Some((_, arg)) = params.iter().find(|(name, _)| *name == slot.name) else {
+                continue;
+            };
+
+            let content = match arg {
+                ComponentArg::Attribute(value) => {
+                    let mut attributes = HashMap::new();
+                    let inner_html = match slot.mode.strip_prefix("attr:") {
+                        Some(attr_name) => {
+                            attributes.insert(attr_name.to_string(), value.clone());
+                            String::new()
+                        }
+                        None if slot.mode == "text" => escape_html_text(value),
+                        None => value.clone(),
+                    };
+                    PageSlotContent {
+                        tag: slot.layout_tag.clone(),
+                        inner_html,
+                        attributes,
+                        original_html: None,
+                        closing_style: slot.closing_style,
+                    }
+                }
+                ComponentArg::Provider(content) => content.clone(),
+            };
+            fills.insert(slot.name.clone(), content);
+        }
+
+        Ok(self.merge_slots(component_html, &slots, &fills))
+    }
+
+    /// Turns the (already include-expanded) child content of an include into component
+    /// arguments: top-level `for-slot` children fill named placeholders and everything
+    /// else fills the `children` placeholder. Works on the source text so table rows,
+    /// list items and options keep their place.
+    fn component_child_args(&self, inner: &str) -> Vec<(String, ComponentArg)> {
+        let providers: Vec<SlotSpan> = provider_source_spans(inner)
+            .into_iter()
+            .filter(|span| span.depth == 0)
+            .collect();
+
+        let children_content = |inner_html: String| PageSlotContent {
+            tag: String::new(),
+            inner_html,
+            attributes: HashMap::new(),
+            original_html: None,
+            closing_style: SlotClosingStyle::Explicit,
+        };
+
+        if providers.is_empty() {
+            return vec![(
+                CHILDREN_SLOT.to_string(),
+                ComponentArg::Provider(children_content(inner.to_string())),
+            )];
+        }
+
+        let mut args = Vec::new();
+        let mut remaining = String::new();
+        let mut last_end = 0;
+        for span in providers {
+            let open_tag = &inner[span.start..span.inner_start];
+            let attributes = open_tag_attributes(open_tag);
+            let name = attributes.get("for-slot").cloned().unwrap_or(span.slot);
+            remaining.push_str(&inner[last_end..span.start]);
+            last_end = span.end;
+            args.push((
+                name,
+                ComponentArg::Provider(PageSlotContent {
+                    tag: open_tag_name(open_tag).to_ascii_lowercase(),
+                    inner_html: inner[span.inner_start..span.inner_end].to_string(),
+                    attributes,
+                    original_html: None,
+                    closing_style: SlotClosingStyle::Explicit,
+                }),
+            ));
+        }
+        remaining.push_str(&inner[last_end..]);
+
+        if !remaining.trim().is_empty() {
+            args.push((
+                CHILDREN_SLOT.to_string(),
+                ComponentArg::Provider(children_content(remaining.trim().to_string())),
+            ));
+        }
+
+        args
+    }
+
+    fn default_slot_provider(&self, slot: &SlotSpec) -> PageSlotContent {
+        let mut attributes: HashMap<String, String> = HashMap::new();
+        attributes.insert("for-slot".to_string(), slot.name.clone());
+
+        if let Some(attr_name) = slot.mode.strip_prefix("attr:") {
+            attributes.insert(attr_name.to_string(), String::new());
+        }
+
+        // Keep defaults blank so normalized pages clearly signal fields to fill in.
+        PageSlotContent {
+            tag: slot.layout_tag.clone(),
+            inner_html: String::new(),
+            attributes,
+            original_html: None,
+            closing_style: slot.closing_style,
+        }
+    }
+
+    /// Fills every slot element of `html`. Slots without an entry in `fills` (and
+    /// repeats of a filled slot) keep their default content; only the slot markers
+    /// are removed.
+    fn merge_slots(
+        &self,
+        html: &str,
+        slots: &[SlotSpec],
+        fills: &HashMap<String, PageSlotContent>,
+    ) -> String {
+        let mut merged = String::with_capacity(html.len());
+        render_template(&compile_template(html, slots), fills, &mut merged);
+        merged
+    }
+
+    fn copy_assets_diff(&self, assets: &[PathBuf]) {
+        let inline_only = self.state.lock().unwrap().inline_only.clone();
+
+        let logs = parallel_map(assets, |path| {
+            let mut log = Vec::new();
+            let rel_path = path.strip_prefix(&self.src_dir).unwrap();
+            let dest = self.out_dir.join(rel_path);
+
+            if !inline_only.is_empty()
+                && path
+                    .canonicalize()
+                    .map(|canonical| inline_only.contains(&canonical))
+                    .unwrap_or(false)
+            {
+                if dest.is_file() {
+                    match fs::remove_file(&dest) {
+                        Ok(_) => log.push(LogLine::Out(format!(
+                            "[Cleanup] Removed inline-only {}",
+                            rel_path.display()
+                        ))),
+                        Err(e) => log.push(LogLine::Err(format!(
+                            "[Warn] Failed to remove {}: {}",
+                            rel_path.display(),
+                            e
+                        ))),
+                    }
+                }
+                return log;
+            }
+
+            let (known_source, known_copy) = {
+                let state = self.state.lock().unwrap();
+                (
+                    state.file_stamps.get(rel_path).cloned(),
+                    state.asset_copies.get(rel_path).cloned(),
+                )
+            };
+            let Some(source) = self.file_stamp(path, known_source.as_ref()) else {
+                return log;
+            };
+
+            // A copy whose stamp is unchanged since it was written from the same
+            // content needs neither file read.
+            let known_copy = known_copy.filter(|copy| copy.hash == source.hash);
+            let mut copy = self
+                .file_stamp(&dest, known_copy.as_ref())
+                .filter(|copy| copy.hash == source.hash);
+
+            if copy.is_none() {
+                let _ = fs::create_dir_all(dest.parent().unwrap());
+                if let Err(e) = fs::copy(path, &dest) {
+                    log.push(LogLine::Err(format!("[Error] {}", e)));
+                } else {
+                    log.push(LogLine::Out(format!("📁 Copied {}", rel_path.display())));
+                    copy = fs::metadata(&dest).ok().map(|metadata| FileStamp {
+                        len: metadata.len(),
+                        modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                        hash: source.hash.clone(),
+                    });
+                }
+            }
+
+            let mut state = self.state.lock().unwrap();
+            state.file_stamps.insert(rel_path.to_path_buf(), source);
+            match copy {
+                Some(copy) => state.asset_copies.insert(rel_path.to_path_buf(), copy),
+                None => state.asset_copies.remove(rel_path),
+            };
+            log
+        });
+        for log in logs {
+            print_log(&log);
+        }
+    }
+
+    fn clean_output_dir(&self, sources: &SourceFiles) {
+        let expected = self.expected_output_set(sources);
+
+        if !self.out_dir.exists() {
+            let _ = fs::create_dir_all(&self.out_dir);
+            return;
+        }
+
+        let mut files_to_remove = Vec::new();
+        for entry in WalkDir::new(&self.out_dir)
+            .min_depth(1)
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            let path = entry.path();
+            let rel = match path.strip_prefix(&self.out_dir) {
+                Ok(p) => p.to_path_buf(),
+                Err(_) => continue,
+            };
+
+            if path.is_file() && !expected.contains(&rel) {
+                files_to_remove.push(path.to_path_buf());
+            }
+        }
+
+        for file in files_to_remove {
+            let rel = file.strip_prefix(&self.out_dir).unwrap_or(file.as_path());
+            if let Err(e) = fs::remove_file(&file) {
+                eprintln!("[Warn] Failed to remove {}: {}", rel.display(), e);
+            } else {
+                println!("[Cleanup] Removed {}", rel.display());
+            }
+        }
+
+        // Remove empty directories deepest first
+        let mut dirs: Vec<PathBuf> = WalkDir::new(&self.out_dir)
+            .min_depth(1)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_dir())
+            .map(|e| e.path().to_path_buf())
+            .collect();
+
+        dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+
+        for dir in dirs {
+            if let Ok(mut entries) = fs::read_dir(&dir) {
+                if entries.next().is_none() {
+                    let _ = fs::remove_dir(&dir);
+                }
+            }
+        }
+    }
+
+    fn remove_output_for_path(&self, path: &Path) {
+        if let Some(file_name) = path.file_name().and_then(|n| n.to_str()) {
+            if file_name.eq_ignore_ascii_case("_layout.html") {
+                return;
+            }
+        }
+
+        let rel_path = if let Ok(rel) = path.strip_prefix(&self.src_dir) {
+            output_rel_path(rel)
+        } else if let Some(name) = path.file_name() {
+            output_rel_path(Path::new(name))
+        } else {
+            return;
+        };
+
+        let dest = self.out_dir.join(&rel_path);
+        if !dest.exists() {
+            return;
+        }
+
+        let result = if dest.is_dir() {
+            fs::remove_dir_all(&dest)
+        } else {
+            fs::remove_file(&dest)
+        };
+
+        match result {
+            Ok(_) => println!("[Cleanup] Removed {}", rel_path.display()),
+            Err(e) => eprintln!("[Error] Failed to remove {}: {}", rel_path.display(), e),
+        }
+    }
+
+    /// `--cache-dir`, or a file named after the output folder in a
+    /// `.site-compiler-cache` folder beside it. The cache lists source paths and
+    /// hashes, so by default it stays out of the folder that gets deployed.
+    fn cache_path(&self) -> PathBuf {
+        if let Some(dir) = &self.options.cache_dir {
+            return dir.join(CACHE_FILE_NAME);
+        }
+        let out_dir = std::path::absolute(&self.out_dir).unwrap_or_else(|_| self.out_dir.clone());
+        match (out_dir.parent(), out_dir.file_name()) {
+            (Some(parent), Some(name)) => parent.join(CACHE_FILE_NAME).join(name),
+            _ => out_dir.join(CACHE_FILE_NAME),
+        }
+    }
+
+    /// The cache file's path relative to the output folder, when `--cache-dir` keeps
+    /// it there, so output cleanup leaves it alone.
+    fn cache_path_in_output(&self) -> Option<PathBuf> {
+        let cache = std::path::absolute(self.cache_path()).ok()?;
+        let out_dir = std::path::absolute(&self.out_dir).ok()?;
+        cache
+            .strip_prefix(out_dir)
+            .ok()
+            .map(|rel| rel.to_path_buf())
+    }
+
+    /// Identifies the compiler version and build options a cache was written with,
+    /// since either can change every output.
+    fn cache_header(&self) -> String {
+        let options = Sha256::digest(format!("{:?}", self.options).as_bytes());
+        format!(
+            "site-compiler-cache 3 {} {}",
+            env!("CARGO_PKG_VERSION"),
+            to_hex(&options)
+        )
+    }
+
+    /// The stamp of `path`, reusing the hash of `known` when the size and
+    /// modification time still match it. `None` when the file is gone.
+    fn file_stamp(&self, path: &Path, known: Option<&FileStamp>) -> Option<FileStamp> {
+        let metadata = fs::metadata(path).ok().filter(|m| m.is_file())?;
+        let len = metadata.len();
+        let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        if let Some(known) = known.filter(|known| known.len == len && known.modified == modified) {
+            return Some(known.clone());
+        }
+        Some(FileStamp {
+            len,
+            modified,
+            hash: self.file_hash(path),
+        })
+    }
+
+    /// Source files plus the include targets outside the source folder (through
+    /// `../` or an outside `--include-root`), which the build cache must check too.
+    fn stamped_files(&self, sources: &SourceFiles, deps: &DependencyGraph) -> Vec<PathBuf> {
+        let src_dir_canonical = self
+            .src_dir
+            .canonicalize()
+            .unwrap_or_else(|_| self.src_dir.clone());
+        let mut external: Vec<PathBuf> = deps
+            .includes
+            .values()
+            .flatten()
+            .filter(|target| !target.starts_with(&src_dir_canonical) && target.is_file())
+            .cloned()
+            .collect();
+        external.sort();
+        external.dedup();
+
+        let mut files = sources.all_files();
+        files.extend(external);
+        files
+    }
+
+    /// Key of `path` in the file stamps: relative to the source folder, or the
+    /// absolute path for include targets outside it.
+    fn stamp_key(&self, path: &Path) -> PathBuf {
+        path.strip_prefix(&self.src_dir)
+            .unwrap_or(path)
+            .to_path_buf()
+    }
+
+    /// Restamps `paths` for the next cache save, or every file from `stamped_files`
+    /// when `replace_all` is set. Paths that no longer exist are dropped.
+    fn update_file_stamps(&self, paths: &[PathBuf], replace_all: bool) {
+        let stamps = parallel_map(paths, |path| {
+            let rel = self.stamp_key(path);
+            let known = self.state.lock().unwrap().file_stamps.get(&rel).cloned();
+            let stamp = self.file_stamp(path, known.as_ref());
+            (rel, stamp)
+        });
+        let mut state = self.state.lock().unwrap();
+        if replace_all {
+            state.file_stamps.clear();
+        }
+        for (rel, stamp) in stamps {
+            match stamp {
+                Some(stamp) => state.file_stamps.insert(rel, stamp),
+                None => state.file_stamps.remove(&rel),
+            };
+        }
+    }
+
+    /// Writes source hashes, output hashes, inlined assets and the dependency graph
+    /// as tab-separated lines. Dependency paths are stored relative to the source
+    /// folder where possible so the cache survives moving the project.
+    fn save_build_cache(&self, sources: &SourceFiles) {
+        let src_dir_canonical = self
+            .src_dir
+            .canonicalize()
+            .unwrap_or_else(|_| self.src_dir.clone());
+        let stored = |path: &Path| -> String {
+            path.strip_prefix(&src_dir_canonical)
+                .unwrap_or(path)
+                .to_string_lossy()
+                .to_string()
+        };
+        let outputs: HashSet<PathBuf> = sources
+            .pages
+            .iter()
+            .chain(&sources.passthrough)
+            .filter_map(|path| path.strip_prefix(&self.src_dir).ok())
+            .map(output_rel_path)
+            .collect();
+
+        let mut lines = vec![self.cache_header()];
+        {
+            let mut state = self.state.lock().unwrap();
+            state.output_hashes.retain(|rel, _| outputs.contains(rel));
+            let assets: HashSet<&Path> = sources
+                .assets
+                .iter()
+                .filter_map(|path| path.strip_prefix(&self.src_dir).ok())
+                .collect();
+            state
+                .asset_copies
+                .retain(|rel, _| assets.contains(rel.as_path()));
+
+            let mut records = Vec::new();
+            for (rel, stamp) in &state.file_stamps {
+                records.push(format!(
+                    "file\t{}\t{}",
+                    rel.to_string_lossy(),
+                    stamp.to_fields()
+                ));
+            }
+            for (rel, stamp) in &state.asset_copies {
+                records.push(format!(
+                    "copy\t{}\t{}",
+                    rel.to_string_lossy(),
+                    stamp.to_fields()
+                ));
+            }
+            for (rel, hash) in &state.output_hashes {
+                records.push(format!(
+                    "output\t{}\t{}",
+                    rel.to_string_lossy(),
+                    to_hex(hash)
+                ));
+            }
+            for asset in &state.inlined_assets {
+                records.push(format!("inlined\t{}", stored(asset)));
+            }
+            for asset in &state.inline_only {
+                records.push(format!("inline-only\t{}", stored(asset)));
+            }
+            for (from, targets) in &state.deps.includes {
+                for to in targets {
+                    records.push(format!("include\t{}\t{}", stored(from), stored(to)));
+                }
+            }
+            for (page, layout) in &state.deps.page_layouts {
+                records.push(format!("layout\t{}\t{}", stored(page), stored(layout)));
+            }
+            records.sort();
+            lines.extend(records);
+        }
+
+        let path = self.cache_path();
+        let temp = path.with_extension("tmp");
+        let result = path
+            .parent()
+            .map(fs::create_dir_all)
+            .unwrap_or(Ok(()))
+            .and_then(|_| fs::write(&temp, lines.join("\n") + "\n"))
+            .and_then(|_| fs::rename(&temp, &path));
+        if let Err(e) = result {
+            eprintln!("[Warn] Failed to write build cache: {}", e);
+        }
+    }
+
+    /// Reads the cache file. `None` when it is missing, unreadable, or was written
+    /// by another compiler version or with other options.
+    fn load_build_cache(&self) -> Option<BuildCache> {
+        let text = fs::read_to_string(self.cache_path()).ok()?;
+        let mut lines = text.lines();
+        if lines.next()? != self.cache_header() {
+            return None;
+        }
+
+        let src_dir_canonical = self
+            .src_dir
+            .canonicalize()
+            .unwrap_or_else(|_| self.src_dir.clone());
+        let mut cache = BuildCache::default();
+        for line in lines {
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields.as_slice() {
+                ["file", rel, len, modified, hash] => {
+                    let stamp = FileStamp::from_fields(len, modified, hash)?;
+                    cache.file_stamps.insert(PathBuf::from(rel), stamp);
+                }
+                ["copy", rel, len, modified, hash] => {
+                    let stamp = FileStamp::from_fields(len, modified, hash)?;
+                    cache.asset_copies.insert(PathBuf::from(rel), stamp);
+                }
+                ["output", rel, hash] => {
+                    cache
+                        .output_hashes
+                        .insert(PathBuf::from(rel), from_hex(hash)?);
+                }
+                ["inlined", path] => {
+                    cache.inlined_assets.insert(src_dir_canonical.join(path));
+                }
+                ["inline-only", path] => {
+                    cache.inline_only.insert(src_dir_canonical.join(path));
+                }
+                ["include", from, to] => cache
+                    .deps
+                    .record_include(&src_dir_canonical.join(from), &src_dir_canonical.join(to)),
+                ["layout", page, layout] => {
+                    cache
+                        .deps
+                        .page_layouts
+                        .insert(src_dir_canonical.join(page), src_dir_canonical.join(layout));
+                }
+                _ => return None,
+            }
+        }
+        Some(cache)
+    }
+
+    /// Starts from the saved build cache instead of a full build: sources whose hash
+    /// changed, new and deleted sources, and pages whose output is missing, was
+    /// edited, or failed last time go through an incremental build. Returns `None`
+    /// when there is no usable cache.
+    fn resume_from_cache(&self, sources: &SourceFiles) -> Option<bool> {
+        // A full build reports output collisions and fails.
+        if self.options.no_cache || !sources.collisions.is_empty() {
+            return None;
+        }
+        let cache = self.load_build_cache()?;
+        let start = Instant::now();
+
+        let files = self.stamped_files(sources, &cache.deps);
+        let stamps = parallel_map(&files, |path| {
+            self.file_stamp(path, cache.file_stamps.get(&self.stamp_key(path)))
+        });
+
+        let mut changed = HashSet::new();
+        let mut current = HashMap::new();
+        for (path, stamp) in files.iter().zip(stamps) {
+            let Some(stamp) = stamp else {
+                continue;
+            };
+            let rel = self.stamp_key(path);
+            if cache.file_stamps.get(&rel).map(|known| &known.hash) != Some(&stamp.hash) {
+                changed.insert(path.clone());
+            }
+            current.insert(rel, stamp);
+        }
+        for rel in cache.file_stamps.keys() {
+            if !current.contains_key(rel) {
+                changed.insert(self.src_dir.join(rel));
+            }
+        }
+
+        let outputs: Vec<&PathBuf> = sources.pages.iter().chain(&sources.passthrough).collect();
+        let stale = parallel_map(&outputs, |path| {
+            let Ok(rel) = path.strip_prefix(&self.src_dir) else {
+                return false;
+            };
+            let out_rel = output_rel_path(rel);
+            match cache.output_hashes.get(&out_rel) {
+                Some(hash) => {
+                    let dest = self.out_dir.join(&out_rel);
+                    !dest.is_file() || self.file_hash(&dest) != *hash
+                }
+                None => true,
+            }
+        });
+        for (path, stale) in outputs.into_iter().zip(stale) {
+            if stale {
+                changed.insert(path.clone());
+            }
+        }
+
+        {
+            let mut state = self.state.lock().unwrap();
+            state.file_stamps = current;
+            state.asset_copies = cache.asset_copies;
+            state.output_hashes = cache.output_hashes;
+            state.inlined_assets = cache.inlined_assets;
+            state.inline_only = cache.inline_only;
+            state.deps = cache.deps;
+        }
+
+        if !changed.is_empty() {
+            println!(
+                "[Cache] {} file(s) changed since the last build",
+                changed.len()
+            );
+            return Some(self.build_sources(Some(&changed), sources));
+        }
+
+        self.print_scan_warnings(sources);
+        self.copy_assets_diff(&sources.assets);
+        let ok = !self.options.report_unused || self.report_unused(sources);
+        self.save_build_cache(sources);
+        println!(
+            "[Cache] Up to date in {} ms, {} file(s) unchanged.\n",
+            format_with_commas(start.elapsed().as_millis()),
+            files.len()
+        );
+        Some(ok)
+    }
+
+    fn expected_output_set(&self, sources: &SourceFiles) -> HashSet<PathBuf> {
+        sources
+            .pages
+            .iter()
+            .chain(&sources.passthrough)
+            .chain(&sources.assets)
+            .filter_map(|path| path.strip_prefix(&self.src_dir).ok())
+            .map(output_rel_path)
+            .chain(self.cache_path_in_output())
+            .collect()
+    }
+
+    fn file_hash(&self, path: &Path) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        if let Ok(mut file) = fs::File::open(path) {
+            let mut buffer = [0; 8192];
+            while let Ok(n) = file.read(&mut buffer) {
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buffer[..n]);
+            }
+        }
+        hasher.finalize().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn make_temp_dir(name: &str) -> PathBuf {
+        let unique = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("html-slot-compiler-{name}-{unique}"));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn make_compiler(root: &Path) -> Compiler {
+        let src_dir = root.join("src");
+        let out_dir = root.join("dist");
+        fs::create_dir_all(&src_dir).unwrap();
+
+        Compiler::new(src_dir, out_dir, BuildOptions::default())
+    }
+
+    #[test]
+    fn expands_recursive_includes_and_skips_component_output() {
+        let root = make_temp_dir("recursive-include");
+        let compiler = make_compiler(&root);
+
+        fs::create_dir_all(compiler.src_dir.join("components")).unwrap();
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"
+<!DOCTYPE html>
+<html>
+  <body>
+    <main slot="content"></main>
+  </body>
+</html>
+"#,
+        )
+        .unwrap();
+
+        fs::write(
+            compiler.src_dir.join("components/card.html"),
+            r#"<article class="card"><include src="badge.html" /></article>"#,
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("components/badge.html"),
+            r#"<span class="badge">Included</span>"#,
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("index.html"),
+            r#"<main for-slot="content"><include src="components/card.html" /></main>"#,
+        )
+        .unwrap();
+
+        assert!(compiler.build_once(None));
+
+        let built = fs::read_to_string(compiler.out_dir.join("index.html")).unwrap();
+        assert!(built
+            .contains(r#"<article class="card"><span class="badge">Included</span></article>"#));
+        assert!(!compiler.out_dir.join("components/card.html").exists());
+        assert!(!compiler.out_dir.join("components/badge.html").exists());
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn reports_include_cycles() {
+        let root = make_temp_dir("include-cycle");
+        let compiler = make_compiler(&root);
+
+        fs::create_dir_all(compiler.src_dir.join("components")).unwrap();
+        let a_path = compiler.src_dir.join("components/a.html");
+        let b_path = compiler.src_dir.join("components/b.html");
+
+        fs::write(&a_path, r#"<include src="b.html" />"#).unwrap();
+        fs::write(&b_path, r#"<include src="a.html" />"#).unwrap();
+
+        let error = compiler.expand_includes_in_file(&a_path).unwrap_err();
+        assert!(error.contains("Include cycle detected"));
+        assert!(error.contains("a.html"));
+        assert!(error.contains("b.html"));
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn attr_slot_mode_updates_meta_content_without_mangling_tag() {
+        let root = make_temp_dir("attr-slot-meta");
+        let compiler = make_compiler(&root);
+
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"
+<!DOCTYPE html>
+<html>
+  <head>
+    <meta slot="description" slot-mode="attr:content" content="" />
+  </head>
+  <body>
+    <main slot="content"></main>
+  </body>
+</html>
+"#,
+        )
+        .unwrap();
+
+        fs::write(
+            compiler.src_dir.join("index.html"),
+            r#"
+<meta for-slot="description" content="Synthetic benchmark page 001" />
+<main for-slot="content"><p>Hello</p></main>
+"#,
+        )
+        .unwrap();
+
+        assert!(compiler.build_once(None));
+
+        let built = fs::read_to_string(compiler.out_dir.join("index.html")).unwrap();
+        assert!(built.contains(r#"<meta content="Synthetic benchmark page 001" />"#));
+        assert!(!built.contains(r#"/ content=""#));
+        assert_eq!(built.matches("content=").count(), 1);
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn builds_nested_pages_with_the_closest_layout() {
+        let root = make_temp_dir("nested-layout");
+        let compiler = make_compiler(&root);
+        let blog_dir = compiler.src_dir.join("blog");
+        let post_dir = blog_dir.join("posts");
+        fs::create_dir_all(&post_dir).unwrap();
+
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"
+<!DOCTYPE html>
+<html>
+  <body>
+    <header slot="header"></header>
+    <main slot="content"></main>
+  </body>
+</html>
+"#,
+        )
+        .unwrap();
+
+        fs::write(
+            blog_dir.join("_layout.html"),
+            r#"
+<!DOCTYPE html>
+<html>
+  <body class="blog-shell">
+    <aside slot="header"></aside>
+    <article slot="content"></article>
+  </body>
+</html>
+"#,
+        )
+        .unwrap();
+
+        fs::write(
+            post_dir.join("post.html"),
+            r#"
+<section for-slot="header"><h1>Nested Post</h1></section>
+<section for-slot="content"><p>Rendered with the nearest layout.</p></section>
+"#,
+        )
+        .unwrap();
+
+        assert!(compiler.build_once(None));
+
+        let built = fs::read_to_string(compiler.out_dir.join("blog/posts/post.html")).unwrap();
+        assert!(built.contains(r#"<body class="blog-shell">"#));
+        assert!(built.contains(r#"<aside><h1>Nested Post</h1></aside>"#));
+        assert!(built.contains(r#"<article><p>Rendered with the nearest layout.</p></article>"#));
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn slot_aliases_accept_and_rewrite_former_slot_names() {
+        let root = make_temp_dir("slot-aliases");
+        let compiler = make_compiler(&root);
+
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"
+<!DOCTYPE html>
+<html>
+  <body>
+    <header slot="header"></header>
+    <main slot="content" slot-aliases="body, main-content"></main>
+  </body>
+</html>
+"#,
+        )
+        .unwrap();
+
+        let page_path = compiler.src_dir.join("index.html");
+        fs::write(
+            &page_path,
+            r#"<header for-slot=header><h1>Hi</h1></header>
+
+<section data-for-slot="x" for-slot=body class="intro"><p>Renamed</p></section>
+"#,
+        )
+        .unwrap();
+
+        assert!(compiler.build_once(None));
+
+        let built = fs::read_to_string(compiler.out_dir.join("index.html")).unwrap();
+        assert!(built.contains(r#"<main><p>Renamed</p></main>"#));
+        assert!(!built.contains("slot-aliases"));
+
+        let normalized = fs::read_to_string(&page_path).unwrap();
+        assert!(
+            normalized.contains(r#"<section data-for-slot="x" for-slot="content" class="intro">"#)
+        );
+        assert!(!normalized.contains("for-slot=body"));
+
+        let pages = vec![page_path.clone()];
+        let rename = MigrationOp::Rename {
+            from: "header".to_string(),
+            to: "masthead".to_string(),
+        };
+        assert_eq!(
+            compiler
+                .migrate(&pages, &rename, None, false)
+                .unwrap()
+                .len(),
+            1
+        );
+        let migrated = fs::read_to_string(&page_path).unwrap();
+        assert!(migrated.contains(r#"<header for-slot="masthead"><h1>Hi</h1></header>"#));
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn migrate_renames_merges_and_deletes_providers() {
+        let root = make_temp_dir("migrate");
+        let compiler = make_compiler(&root);
+
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><body><header slot="header"></header><main slot="content"></main></body></html>"#,
+        )
+        .unwrap();
+        let page_path = compiler.src_dir.join("index.html");
+        let original = "<header for-slot=\"hero\"><h1>Hi</h1></header>\n\n<main for-slot=\"content\"><p>Body</p></main>\n\n<aside for-slot=\"extra\"><p>More</p></aside>\n";
+        fs::write(&page_path, original).unwrap();
+        let pages = vec![page_path.clone()];
+
+        let rename = MigrationOp::Rename {
+            from: "hero".to_string(),
+            to: "header".to_string(),
+        };
+        let changed = compiler.migrate(&pages, &rename, None, true).unwrap();
+        assert_eq!(changed, vec!["index.html".to_string()]);
+        assert_eq!(fs::read_to_string(&page_path).unwrap(), original);
+
+        compiler.migrate(&pages, &rename, None, false).unwrap();
+        let merge = MigrationOp::Merge {
+            from: "extra".to_string(),
+            into: "content".to_string(),
+        };
+        compiler.migrate(&pages, &merge, None, false).unwrap();
+
+        let migrated = fs::read_to_string(&page_path).unwrap();
+        assert_eq!(
+            migrated,
+            "<header for-slot=\"header\"><h1>Hi</h1></header>\n\n<main for-slot=\"content\"><p>Body</p><p>More</p></main>\n"
+        );
+
+        let delete = MigrationOp::Delete {
+            slot: "missing".to_string(),
+        };
+        assert!(compiler
+            .migrate(&pages, &delete, None, false)
+            .unwrap()
+            .is_empty());
+
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(parse_migration_op(&args(&["merge-slots", "content", "content"])).is_err());
+        let onto_itself = MigrationOp::Merge {
+            from: "content".to_string(),
+            into: "content".to_string(),
+        };
+        assert!(compiler.migrate(&pages, &onto_itself, None, false).is_err());
+        assert_eq!(fs::read_to_string(&page_path).unwrap(), migrated);
+
+        fs::write(
+            &page_path,
+            "<main for-slot=\"content\" class=\"a\"><p>Body</p></main>\n\n<aside for-slot=\"extra\" class=\"b\" id=\"more\"><p>More</p></aside>\n",
+        )
+        .unwrap();
+        compiler.migrate(&pages, &merge, None, false).unwrap();
+        let merged = fs::read_to_string(&page_path).unwrap();
+        assert!(merged.contains(r#"id="more""#));
+        assert!(merged.contains(r#"class="a""#));
+        assert!(!merged.contains("extra"));
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn migrate_and_deps_take_build_options() {
+        let root = make_temp_dir("subcommand-options");
+        let src_dir = root.join("src");
+        fs::create_dir_all(src_dir.join("docs")).unwrap();
+        fs::create_dir_all(root.join("shared")).unwrap();
+        fs::write(
+            src_dir.join("_layout.html"),
+            r#"<html><body><main slot="content"></main></body></html>"#,
+        )
+        .unwrap();
+        fs::write(root.join("shared/card.html"), "<p>Card</p>").unwrap();
+        let page_path = src_dir.join("index.html");
+        fs::write(
+            &page_path,
+            r#"<main for-slot="content"><include src="card.html" /></main>"#,
+        )
+        .unwrap();
+        // A documentation partial that shows a provider as an example.
+        let example_path = src_dir.join("docs/example.html");
+        let example = r#"<main for-slot="content">example</main>"#;
+        fs::write(&example_path, example).unwrap();
+
+        let args = |extra: &[&str]| -> Vec<String> {
+            let mut args = vec![src_dir.display().to_string()];
+            args.extend(extra.iter().map(|arg| arg.to_string()));
+            args
+        };
+        assert_eq!(run_deps(&args(&[])), 2);
+        assert_eq!(
+            run_deps(&args(&[
+                "--include-root",
+                "../shared",
+                "--partials",
+                "docs/**"
+            ])),
+            0
+        );
+        assert_eq!(run_deps(&args(&["--partials"])), 1);
+        assert_eq!(run_deps(&args(&["--bogus"])), 1);
+
+        assert_eq!(
+            run_migrate(&args(&[
+                "rename-slot",
+                "content",
+                "body",
+                "--partials",
+                "docs/**",
+            ])),
+            0
+        );
+        assert!(fs::read_to_string(&page_path)
+            .unwrap()
+            .contains(r#"for-slot="body""#));
+        assert_eq!(fs::read_to_string(&example_path).unwrap(), example);
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn comment_policy_quarantines_unknown_slots_and_builds_page() {
+        let root = make_temp_dir("quarantine");
+        let mut compiler = make_compiler(&root);
+        compiler.options.unknown_slots = UnknownSlotPolicy::Comment;
+
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><body><main slot="content"></main></body></html>"#,
+        )
+        .unwrap();
+        let page_path = compiler.src_dir.join("index.html");
+        fs::write(
+            &page_path,
+            "<main for-slot=\"content\"><p>Kept</p></main>\n\n<aside for-slot=\"legacy\"><p>Old</p></aside>\n",
+        )
+        .unwrap();
+
+        assert!(compiler.build_once(None));
+
+        let built = fs::read_to_string(compiler.out_dir.join("index.html")).unwrap();
+        assert!(built.contains("<main><p>Kept</p></main>"));
+        assert!(!built.contains("Old"));
+
+        let normalized = fs::read_to_string(&page_path).unwrap();
+        assert_eq!(
+            normalized,
+            "<main for-slot=\"content\"><p>Kept</p></main>\n\n<!-- quarantined-slots\n<aside for-slot=\"legacy\"><p>Old</p></aside>\n-->\n"
+        );
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn include_attributes_fill_component_parameters() {
+        let root = make_temp_dir("component-params");
+        let compiler = make_compiler(&root);
+
+        fs::create_dir_all(compiler.src_dir.join("components")).unwrap();
+        fs::write(
+            compiler.src_dir.join("components/card.html"),
+            r#"<a class="card" slot="href" slot-mode="attr:href" href="/"><h2 slot="title" slot-mode="text"></h2><p slot="note" slot-optional>Default note</p></a>"#,
+        )
+        .unwrap();
+
+        let dir = compiler.src_dir.clone();
+        let mut stack = Vec::new();
+        let expanded = compiler
+            .expand_includes_in_html(
+                r#"<include src="components/card.html" title="Tom &amp; <Jerry>" href="/x" />"#,
+                &dir,
+                &mut stack,
+            )
+            .unwrap();
+        assert_eq!(
+            expanded,
+            r#"<a class="card" href="/x"><h2>Tom &amp; &lt;Jerry&gt;</h2><p>Default note</p></a>"#
+        );
+
+        let unknown = compiler
+            .expand_includes_in_html(
+                r#"<include src="components/card.html" title="Hi" href="/x" colour="red" />"#,
+                &dir,
+                &mut stack,
+            )
+            .unwrap_err();
+        assert!(unknown.contains("Unknown parameters for include components/card.html: colour"));
+
+        let missing = compiler
+            .expand_includes_in_html(
+                r#"<include src="components/card.html" title="Hi" />"#,
+                &dir,
+                &mut stack,
+            )
+            .unwrap_err();
+        assert!(
+            missing.contains("Missing required parameters for include components/card.html: href")
+        );
+
+        let bare = compiler
+            .expand_includes_in_html(
+                r#"<include src="components/card.html" />"#,
+                &dir,
+                &mut stack,
+            )
+            .unwrap_err();
+        assert!(bare.contains("Missing required parameters for include components/card.html"));
+
+        // Inside a layout a bare include keeps its slots, and attr slots drop their
+        // default content once filled.
+        fs::write(
+            compiler.src_dir.join("components/link.html"),
+            r#"<a slot="link" slot-mode="attr:href" href="/">Read more</a>"#,
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><body><include src="components/link.html" /><main slot="content"></main></body></html>"#,
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("index.html"),
+            "<a for-slot=\"link\" href=\"/next\"></a>\n\n<main for-slot=\"content\">Hi</main>\n",
+        )
+        .unwrap();
+        assert!(compiler.build_once(None));
+        let built = fs::read_to_string(compiler.out_dir.join("index.html")).unwrap();
+        assert!(built.contains(r#"<a href="/next"></a><main>Hi</main>"#));
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn include_child_content_fills_children_and_named_placeholders() {
+        let root = make_temp_dir("component-children");
+        let compiler = make_compiler(&root);
+
+        fs::create_dir_all(compiler.src_dir.join("components")).unwrap();
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><body><main slot="content"></main></body></html>"#,
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("components/callout.html"),
+            r#"<aside class="callout"><h2 slot="heading" slot-optional>Note</h2><div class="body" slot="children"></div></aside>"#,
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("index.html"),
+            r#"<main for-slot="content"><include src="components/callout.html"><h3 for-slot="heading">Heads up</h3><div>Outer <include src="components/callout.html"><em>inner</em></include></div></include></main>"#,
+        )
+        .unwrap();
+
+        assert!(compiler.build_once(None));
+
+        let built = fs::read_to_string(compiler.out_dir.join("index.html")).unwrap();
+        assert!(built.contains(
+            r#"<main><aside class="callout"><h2>Heads up</h2><div class="body"><div>Outer <aside class="callout"><h2>Note</h2><div class="body"><em>inner</em></div></aside></div></div></aside></main>"#
+        ));
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn include_children_keep_table_and_list_parts_and_only_top_level_args() {
+        let root = make_temp_dir("component-fragments");
+        let compiler = make_compiler(&root);
+
+        fs::create_dir_all(compiler.src_dir.join("components")).unwrap();
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><body><main slot="content"></main></body></html>"#,
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("components/table.html"),
+            r#"<table><caption slot="caption" slot-optional></caption><tbody slot="children"></tbody></table>"#,
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("components/list.html"),
+            r#"<ul slot="children"></ul>"#,
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("components/select.html"),
+            r#"<select slot="children"></select>"#,
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("index.html"),
+            concat!(
+                r#"<main for-slot="content">"#,
+                r#"<include src="components/table.html"><span for-slot="caption">Totals</span><tr><td>1</td></tr></include>"#,
+                r#"<include src="components/list.html"><li><b for-slot="caption">kept</b></li></include>"#,
+                r#"<include src="components/select.html"><option value="a">A</option></include>"#,
+                r#"</main>"#,
+            ),
+        )
+        .unwrap();
+
+        assert!(compiler.build_once(None));
+
+        let built = fs::read_to_string(compiler.out_dir.join("index.html")).unwrap();
+        assert!(built.contains(
+            r#"<table><caption>Totals</caption><tbody><tr><td>1</td></tr></tbody></table>"#
+        ));
+        assert!(built.contains(r#"<ul><li><b for-slot="caption">kept</b></li></ul>"#));
+        assert!(built.contains(r#"<select><option value="a">A</option></select>"#));
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn resolves_root_relative_and_include_root_paths() {
+        let root = make_temp_dir("include-roots");
+        let mut compiler = make_compiler(&root);
+        compiler.options.include_roots = vec![PathBuf::from("partials"), PathBuf::from("shared")];
+
+        let nested = compiler.src_dir.join("docs/guides/setup");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir_all(compiler.src_dir.join("components")).unwrap();
+        fs::create_dir_all(compiler.src_dir.join("shared")).unwrap();
+        fs::write(compiler.src_dir.join("components/nav.html"), "<nav></nav>").unwrap();
+        fs::write(
+            compiler.src_dir.join("shared/footer.html"),
+            "<footer></footer>",
+        )
+        .unwrap();
+
+        let mut stack = Vec::new();
+        let expanded = compiler
+            .expand_includes_in_html(
+                r#"<include src="/components/nav.html" /><include src="footer.html" />"#,
+                &nested,
+                &mut stack,
+            )
+            .unwrap();
+        assert_eq!(expanded, "<nav></nav><footer></footer>");
+
+        let error = compiler
+            .expand_includes_in_html(r#"<include src="missing.html" />"#, &nested, &mut stack)
+            .unwrap_err();
+        assert!(error.contains("Include not found: missing.html"));
+        assert!(error.contains("docs/guides/setup/missing.html"));
+        assert!(error.contains("partials/missing.html"));
+        assert!(error.contains("shared/missing.html"));
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn inlines_svg_css_and_text_includes_and_skips_inline_only_copies() {
+        let root = make_temp_dir("inline-assets");
+        let compiler = make_compiler(&root);
+
+        fs::create_dir_all(compiler.src_dir.join("assets")).unwrap();
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><head><include src="assets/critical.css" wrap /></head><body><main slot="content"></main></body></html>"#,
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("assets/critical.css"),
+            "body{margin:0}\n",
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("assets/icon.svg"),
+            "<?xml version=\"1.0\"?>\n<!DOCTYPE svg PUBLIC \"-//W3C//DTD SVG 1.1//EN\" \"x.dtd\">\n<svg><path d=\"M0\"/></svg>\n",
+        )
+        .unwrap();
+        fs::write(compiler.src_dir.join("assets/notice.txt"), "a < b").unwrap();
+        fs::write(compiler.src_dir.join("assets/logo.svg"), "<svg></svg>").unwrap();
+        fs::write(
+            compiler.src_dir.join("index.html"),
+            r#"<main for-slot="content"><include src="assets/icon.svg" /><include src="assets/notice.txt" /><include src="assets/logo.svg" /><img src="assets/logo.svg"><p>Drawn from icon.svg</p></main>"#,
+        )
+        .unwrap();
+
+        assert!(compiler.build_once(None));
+
+        let built = fs::read_to_string(compiler.out_dir.join("index.html")).unwrap();
+        assert!(built.contains("<head><style>body{margin:0}</style></head>"));
+        assert!(built.contains(r#"<main><svg><path d="M0"></path></svg>a &lt; b<svg></svg>"#));
+        assert!(!built.contains("<?xml"));
+        assert!(!compiler.out_dir.join("assets/critical.css").exists());
+        assert!(!compiler.out_dir.join("assets/icon.svg").exists());
+        assert!(!compiler.out_dir.join("assets/notice.txt").exists());
+        assert!(compiler.out_dir.join("assets/logo.svg").exists());
+
+        // A watch build of a page that starts linking an inlined asset copies it.
+        let index = compiler.src_dir.join("index.html");
+        fs::write(
+            &index,
+            r#"<main for-slot="content"><include src="assets/icon.svg" /><a href="assets/icon.svg">Icon</a></main>"#,
+        )
+        .unwrap();
+        assert!(compiler.build_once(Some(&HashSet::from([index]))));
+        assert!(compiler.out_dir.join("assets/icon.svg").exists());
+        assert!(!compiler.out_dir.join("assets/notice.txt").exists());
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn includes_fragments_by_selector_and_slot_provider() {
+        let root = make_temp_dir("include-fragments");
+        let compiler = make_compiler(&root);
+
+        fs::write(
+            compiler.src_dir.join("pricing.html"),
+            r#"<main for-slot="content"><p>Plans</p><table id="plans"><tbody><tr><td>Pro</td></tr></tbody></table></main>
+<section for-slot="intro"><p>Welcome <b>back</b></p></section>"#,
+        )
+        .unwrap();
+
+        let dir = compiler.src_dir.clone();
+        let mut stack = Vec::new();
+        let expanded = compiler
+            .expand_includes_in_html(
+                r##"<include src="pricing.html" select="#plans" /><include src="pricing.html" from-slot="intro" />"##,
+                &dir,
+                &mut stack,
+            )
+            .unwrap();
+        assert_eq!(
+            expanded,
+            r#"<table id="plans"><tbody><tr><td>Pro</td></tr></tbody></table><p>Welcome <b>back</b></p>"#
+        );
+
+        let error = compiler
+            .expand_includes_in_html(
+                r#"<include src="pricing.html" select=".missing" />"#,
+                &dir,
+                &mut stack,
+            )
+            .unwrap_err();
+        assert!(error.contains("Selector '.missing' matched nothing in include pricing.html"));
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn glob_includes_expand_matching_files_in_order() {
+        let root = make_temp_dir("glob-include");
+        let compiler = make_compiler(&root);
+
+        let announcements = compiler.src_dir.join("partials/announcements");
+        fs::create_dir_all(announcements.join("archive")).unwrap();
+        fs::write(announcements.join("2024-01.html"), "<p>Jan</p>").unwrap();
+        fs::write(announcements.join("2024-02.html"), "<p>Feb</p>").unwrap();
+        fs::write(announcements.join("2024-03.html"), "<p>Mar</p>").unwrap();
+        fs::write(announcements.join("notes.txt"), "skip").unwrap();
+        fs::write(announcements.join("archive/2023-12.html"), "<p>Dec</p>").unwrap();
+
+        let dir = compiler.src_dir.clone();
+        let mut stack = Vec::new();
+        let expand = |html: &str, stack: &mut Vec<PathBuf>| {
+            compiler.expand_includes_in_html(html, &dir, stack).unwrap()
+        };
+
+        assert_eq!(
+            expand(
+                r#"<include src="partials/announcements/*.html" />"#,
+                &mut stack
+            ),
+            "<p>Jan</p><p>Feb</p><p>Mar</p>"
+        );
+        assert_eq!(
+            expand(
+                r#"<include src="/partials/announcements/*.html" reverse limit="2" />"#,
+                &mut stack
+            ),
+            "<p>Mar</p><p>Feb</p>"
+        );
+        assert_eq!(
+            expand(r#"<include src="partials/**/2023-*.html" />"#, &mut stack),
+            "<p>Dec</p>"
+        );
+        assert_eq!(
+            expand(r#"<include src="partials/none/*.html" />"#, &mut stack),
+            ""
+        );
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn layouts_and_pages_sharing_a_component_get_separate_expansions() {
+        let root = make_temp_dir("shared-component");
+        let compiler = make_compiler(&root);
+
+        fs::create_dir_all(compiler.src_dir.join("components")).unwrap();
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><head><include src="/components/meta.html" /></head><body><main slot="content"></main></body></html>"#,
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("components/meta.html"),
+            r#"<meta charset="utf-8"><include src="/components/head.html" />"#,
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("components/head.html"),
+            r#"<link slot="link" slot-optional rel="stylesheet" href="/a.css">"#,
+        )
+        .unwrap();
+        let about = compiler.src_dir.join("about.html");
+        fs::write(
+            &about,
+            r#"<main for-slot="content"><include src="/components/meta.html" /></main>"#,
+        )
+        .unwrap();
+
+        // Expand the page first, as a parallel build may, then load the layout.
+        let page_html = fs::read_to_string(&about).unwrap();
+        let expanded = compiler
+            .expand_includes_in_html(
+                &page_html,
+                &compiler.src_dir,
+                &mut vec![about.canonicalize().unwrap()],
+            )
+            .unwrap();
+        assert!(!expanded.contains(r#"slot="link""#));
+        let layout = compiler.layout_for_page(&about).unwrap();
+        assert!(layout.slots.iter().any(|slot| slot.name == "link"));
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn repeated_layout_slots_are_all_filled() {
+        let root = make_temp_dir("repeated-slots");
+        let compiler = make_compiler(&root);
+
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><head><title slot="title" slot-mode="text"></title></head><body><h1 slot="title" slot-mode="text"></h1><main slot="content"></main></body></html>"#,
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("index.html"),
+            "<title for-slot=\"title\">Hello</title>\n\n<main for-slot=\"content\"><p>Hi</p></main>\n",
+        )
+        .unwrap();
+
+        assert!(compiler.build_once(None));
+        let built = fs::read_to_string(compiler.out_dir.join("index.html")).unwrap();
+        assert!(built.contains("<title>Hello</title>"));
+        assert!(built.contains("<h1>Hello</h1>"));
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn worker_warnings_go_to_the_page_report() {
+        let root = make_temp_dir("page-warnings");
+        let compiler = make_compiler(&root);
+
+        fs::create_dir_all(compiler.src_dir.join("empty")).unwrap();
+        fs::write(
+            compiler.src_dir.join("empty/_layout.html"),
+            "<html><body></body></html>",
+        )
+        .unwrap();
+        let page = compiler.src_dir.join("empty/index.html");
+        fs::write(
+            &page,
+            r#"<main for-slot="content"><include src="missing/*.html" /></main>"#,
+        )
+        .unwrap();
+
+        let report = compiler.build_page(&page);
+        let logged = |text: &str| {
+            report
+                .log
+                .iter()
+                .any(|line| matches!(line, LogLine::Out(line) if line.contains(text)))
+        };
+        assert!(logged("[Warn] No slots in empty/_layout.html"));
+        assert!(logged(
+            "[Warn] Include pattern missing/*.html matched no files"
+        ));
+        assert!(compiler.state.lock().unwrap().deferred_warnings.is_empty());
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn include_cache_is_shared_within_a_build_and_cleared_between_builds() {
+        let root = make_temp_dir("include-cache");
+        let compiler = make_compiler(&root);
+
+        fs::create_dir_all(compiler.src_dir.join("components")).unwrap();
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><body><include src="components/header.html" /><main slot="content"></main></body></html>"#,
+        )
+        .unwrap();
+        let header = compiler.src_dir.join("components/header.html");
+        fs::write(&header, "<header>v1</header>").unwrap();
+        for name in ["a.html", "b.html"] {
+            fs::write(
+                compiler.src_dir.join(name),
+                r#"<main for-slot="content"><include src="components/header.html" /></main>"#,
+            )
+            .unwrap();
+        }
+
+        assert!(compiler.build_once(None));
+        let cached_header = header.canonicalize().unwrap();
+        assert_eq!(
+            compiler
+                .state
+                .lock()
+                .unwrap()
+                .include_cache
+                .get(&(cached_header.clone(), false)),
+            Some(&"<header>v1</header>".to_string())
+        );
+
+        fs::write(&header, "<header>v2</header>").unwrap();
+        let changed = HashSet::from([cached_header]);
+        assert!(compiler.build_once(Some(&changed)));
+
+        for name in ["a.html", "b.html"] {
+            let built = fs::read_to_string(compiler.out_dir.join(name)).unwrap();
+            assert_eq!(built.matches("<header>v2</header>").count(), 2);
+            assert!(!built.contains("v1"));
+        }
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn dependency_graph_limits_rebuilds_to_affected_pages() {
+        let root = make_temp_dir("dependency-graph");
+        let compiler = make_compiler(&root);
+
+        let blog_dir = compiler.src_dir.join("blog");
+        fs::create_dir_all(compiler.src_dir.join("components")).unwrap();
+        fs::create_dir_all(&blog_dir).unwrap();
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><body><main slot="content"></main></body></html>"#,
+        )
+        .unwrap();
+        fs::write(
+            blog_dir.join("_layout.html"),
+            r#"<html><body class="blog"><include src="/components/nav.html" /><main slot="content"></main></body></html>"#,
+        )
+        .unwrap();
+        fs::write(compiler.src_dir.join("components/nav.html"), "<nav></nav>").unwrap();
+        fs::write(
+            compiler.src_dir.join("components/card.html"),
+            "<div>card</div>",
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("index.html"),
+            r#"<main for-slot="content"><include src="components/card.html" /></main>"#,
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("about.html"),
+            r#"<main for-slot="content"><p>About</p></main>"#,
+        )
+        .unwrap();
+        fs::write(
+            blog_dir.join("post.html"),
+            r#"<main for-slot="content"><p>Post</p></main>"#,
+        )
+        .unwrap();
+
+        assert!(compiler.build_once(None));
+
+        let canonical = |rel: &str| compiler.src_dir.join(rel).canonicalize().unwrap();
+        let affected = |rel: &str| {
+            compiler
+                .state
+                .lock()
+                .unwrap()
+                .deps
+                .affected_pages(&canonical(rel))
+        };
+        assert_eq!(
+            affected("components/card.html"),
+            vec![canonical("index.html")]
+        );
+        assert_eq!(
+            affected("components/nav.html"),
+            vec![canonical("blog/post.html")]
+        );
+        assert_eq!(
+            affected("blog/_layout.html"),
+            vec![canonical("blog/post.html")]
+        );
+        assert_eq!(
+            affected("_layout.html"),
+            vec![canonical("about.html"), canonical("index.html")]
+        );
+
+        fs::write(
+            compiler.src_dir.join("components/card.html"),
+            "<div>card v2</div>",
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("about.html"),
+            "<main for-slot=\"content\"></main>",
+        )
+        .unwrap();
+        let stale_about = fs::read_to_string(compiler.out_dir.join("about.html")).unwrap();
+        let changed = HashSet::from([canonical("components/card.html")]);
+        assert!(compiler.build_once(Some(&changed)));
+
+        let index = fs::read_to_string(compiler.out_dir.join("index.html")).unwrap();
+        assert!(index.contains("card v2"));
+        let about = fs::read_to_string(compiler.out_dir.join("about.html")).unwrap();
+        assert_eq!(about, stale_about);
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn hoists_and_dedupes_component_styles_and_scripts() {
+        let root = make_temp_dir("hoist-assets");
+        let compiler = make_compiler(&root);
+
+        fs::create_dir_all(compiler.src_dir.join("components")).unwrap();
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            "<html><head><title>T</title></head><body><main slot=\"content\"></main></body></html>",
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("components/card.html"),
+            r#"<style hoist>.card{color:red}</style><div class="card">Card</div><script hoist src="/js/card.js"></script><script>inline()</script>"#,
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("index.html"),
+            r#"<main for-slot="content"><include src="components/card.html" /><include src="components/card.html" /></main>"#,
+        )
+        .unwrap();
+
+        assert!(compiler.build_once(None));
+
+        let built = fs::read_to_string(compiler.out_dir.join("index.html")).unwrap();
+        assert_eq!(built.matches(".card{color:red}").count(), 1);
+        assert_eq!(built.matches("/js/card.js").count(), 1);
+        assert_eq!(built.matches("inline()").count(), 2);
+        assert!(built.contains("<title>T</title><style>.card{color:red}</style></head>"));
+        assert!(built.contains(r#"<script src="/js/card.js"></script></body>"#));
+        assert!(!built.contains("hoist"));
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn reports_unused_components_and_assets() {
+        let root = make_temp_dir("unused-report");
+        let mut compiler = make_compiler(&root);
+        compiler.options.report_unused = true;
+
+        for dir in ["components", "css", "img", "blog"] {
+            fs::create_dir_all(compiler.src_dir.join(dir)).unwrap();
+        }
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><head><link rel="stylesheet" href="/css/site.css"></head><body><main slot="content"></main></body></html>"#,
+        )
+        .unwrap();
+        fs::write(compiler.src_dir.join("components/used.html"), "<div></div>").unwrap();
+        fs::write(
+            compiler.src_dir.join("components/old-hero.html"),
+            "<div></div>",
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("css/site.css"),
+            "body { background: url('../img/bg.png'); }",
+        )
+        .unwrap();
+        fs::write(compiler.src_dir.join("img/bg.png"), "png").unwrap();
+        fs::write(compiler.src_dir.join("img/logo.png"), "png").unwrap();
+        fs::write(compiler.src_dir.join("img/old.png"), "png").unwrap();
+        fs::write(
+            compiler.src_dir.join("blog/post.html"),
+            r#"<main for-slot="content"><include src="/components/used.html" /><img src="../img/logo.png?v=2"></main>"#,
+        )
+        .unwrap();
+
+        assert!(compiler.build_once(None));
+        let (components, assets) = compiler.find_unused(&compiler.scan_source_files());
+        assert_eq!(components, vec![PathBuf::from("components/old-hero.html")]);
+        assert_eq!(assets, vec![PathBuf::from("img/old.png")]);
+
+        compiler.options.strict_unused = true;
+        assert!(!compiler.build_once(None));
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn debug_builds_mark_includes_and_slots_with_provenance_comments() {
+        let root = make_temp_dir("provenance");
+        let mut compiler = make_compiler(&root);
+        compiler.options.provenance = true;
+
+        fs::create_dir_all(compiler.src_dir.join("components")).unwrap();
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            "<!DOCTYPE html><html><head><title slot=\"title\" slot-mode=\"text\"></title></head><body><main slot=\"content\"></main></body></html>",
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("components/card.html"),
+            "<div class=\"card\"></div>",
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("index.html"),
+            "<title for-slot=\"title\">Home</title><main for-slot=\"content\"><include src=\"components/card.html\" /></main>",
+        )
+        .unwrap();
+
+        assert!(compiler.build_once(None));
+        let output = fs::read_to_string(compiler.out_dir.join("index.html")).unwrap();
+        assert!(output
+            .starts_with("<!DOCTYPE html>\n<!-- page index.html with layout _layout.html -->"));
+        assert!(output.contains("<title>Home</title>"));
+        assert!(output.contains(
+            "<main><!-- begin slot content from index.html --><!-- begin include components/card.html --><div class=\"card\"></div><!-- end include components/card.html --><!-- end slot content --></main>"
+        ));
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn normalization_moves_provider_source_verbatim_with_comments() {
+        let root = make_temp_dir("normalize-verbatim");
+        let compiler = make_compiler(&root);
+
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><head><meta slot="description" slot-mode="attr:content" /></head><body><header slot="header"></header><main slot="content"></main></body></html>"#,
+        )
+        .unwrap();
+        let page = "<main for-slot='content' class=main>\n  <p>Fish &amp; chips&nbsp;<br></p>\n</main>\n\n<!-- Site-wide header -->\n<header for-slot=\"header\"><h1>Hi</h1></header>\n<!-- end of page -->\n";
+        fs::write(compiler.src_dir.join("index.html"), page).unwrap();
+
+        assert!(compiler.build_once(None));
+        let normalized = fs::read_to_string(compiler.src_dir.join("index.html")).unwrap();
+        assert_eq!(
+            normalized,
+            "<meta for-slot=\"description\" content=\"\" />\n\n<!-- Site-wide header -->\n<header for-slot=\"header\"><h1>Hi</h1></header>\n\n<main for-slot='content' class=main>\n  <p>Fish &amp; chips&nbsp;<br></p>\n</main>\n\n<!-- end of page -->\n"
+        );
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn fidelity_mode_copies_slot_content_verbatim() {
+        let root = make_temp_dir("fidelity");
+        let mut compiler = make_compiler(&root);
+        compiler.options.fidelity = true;
+
+        fs::create_dir_all(compiler.src_dir.join("components")).unwrap();
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><body><main slot="content"></main></body></html>"#,
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("components/note.html"),
+            "<P class=note>Fish&nbsp;&amp; chips</P>",
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("index.html"),
+            "<main for-slot=\"content\">\n  <IMG src=a.png alt=''>\n  <include src=\"components/note.html\" />\n</main>",
+        )
+        .unwrap();
+
+        assert!(compiler.build_once(None));
+        let output = fs::read_to_string(compiler.out_dir.join("index.html")).unwrap();
+        assert!(output.contains(
+            "<main>\n  <IMG src=a.png alt=''>\n  <P class=note>Fish&nbsp;&amp; chips</P>\n</main>"
+        ));
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn table_list_and_option_providers_survive_parsing() {
+        let root = make_temp_dir("fragment-context");
+        let compiler = make_compiler(&root);
+
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><head><title slot="title" slot-mode="text"></title></head><body><table><tbody><tr slot="row"></tr></tbody></table><ul><li slot="item"></li></ul><select><option slot="choice"></option></select></body></html>"#,
+        )
+        .unwrap();
+        let page = "<title for-slot=\"title\">Report</title>\n\n<tr for-slot=\"row\"><td>A</td><td>B</td></tr>\n\n<li for-slot=\"item\">One</li>\n\n<option for-slot=\"choice\">X</option>\n";
+        fs::write(compiler.src_dir.join("index.html"), page).unwrap();
+
+        assert!(compiler.build_once(None));
+        let output = fs::read_to_string(compiler.out_dir.join("index.html")).unwrap();
+        assert!(output.contains("<title>Report</title>"));
+        assert!(output.contains("<tr><td>A</td><td>B</td></tr>"));
+        assert!(output.contains("<li>One</li>"));
+        assert!(output.contains("<option>X</option>"));
+        assert_eq!(
+            fs::read_to_string(compiler.src_dir.join("index.html")).unwrap(),
+            page
+        );
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn merge_handles_nested_tags_comments_and_scripts() {
+        let root = make_temp_dir("merge-engine");
+        let compiler = make_compiler(&root);
+
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><body>
+  <!-- <div slot="content"></div> -->
+  <script>const tpl = '<div slot="content"></div>';</script>
+  <div slot="content">
+    <div class="default"><div>nested</div></div>
+  </div>
+  <div class="after"></div>
+</body></html>"#,
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("index.html"),
+            r#"<div for-slot="content"><p>Page</p></div>"#,
+        )
+        .unwrap();
+
+        assert!(compiler.build_once(None));
+        let output = fs::read_to_string(compiler.out_dir.join("index.html")).unwrap();
+        assert!(output.contains(r#"<!-- <div slot="content"></div> -->"#));
+        assert!(output.contains(r#"const tpl = '<div slot="content"></div>';"#));
+        assert!(output.contains("  <div><p>Page</p></div>\n  <div class=\"after\"></div>"));
+
+        let tag = r#"<td data-for-slot="x" for-slot=cell class='a b' hidden>"#;
+        assert_eq!(tag_attribute(tag, "for-slot").as_deref(), Some("cell"));
+        assert_eq!(tag_attribute(tag, "class").as_deref(), Some("a b"));
+        assert_eq!(tag_attribute(tag, "hidden").as_deref(), Some(""));
+        assert_eq!(tag_attribute(tag, "slot"), None);
+        assert_eq!(
+            strip_slot_markers(r#"<p slot=note slot-optional class="x" slot-mode='text'>"#),
+            r#"<p class="x">"#
+        );
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn compiled_layouts_are_reused_until_layout_includes_change() {
+        let root = make_temp_dir("layout-template-cache");
+        let compiler = make_compiler(&root);
+
+        fs::create_dir_all(compiler.src_dir.join("components")).unwrap();
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><body><include src="components/nav.html" /><main slot="content"></main></body></html>"#,
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("components/nav.html"),
+            "<nav>v1</nav>",
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("index.html"),
+            r#"<main for-slot="content"><p>Home</p></main>"#,
+        )
+        .unwrap();
+
+        assert!(compiler.build_once(None));
+        let layout_key = compiler
+            .src_dir
+            .join("_layout.html")
+            .canonicalize()
+            .unwrap();
+        let cached =
+            |compiler: &Compiler| Arc::clone(&compiler.state.lock().unwrap().layouts[&layout_key]);
+        let first = cached(&compiler);
+
+        let page = compiler.src_dir.join("index.html");
+        fs::write(&page, r#"<main for-slot="content"><p>Welcome</p></main>"#).unwrap();
+        assert!(compiler.build_once(Some(&HashSet::from([page.clone()]))));
+        assert!(Arc::ptr_eq(&first, &cached(&compiler)));
 
-        for entry in WalkDir::new(&self.src_dir)
-            .into_iter()
-            .filter_map(|e| e.ok())
-        {
-            let path = entry.path();
+        let nav = compiler.src_dir.join("components/nav.html");
+        fs::write(&nav, "<nav>v2</nav>").unwrap();
+        assert!(compiler.build_once(Some(&HashSet::from([nav]))));
+        assert!(!Arc::ptr_eq(&first, &cached(&compiler)));
 
-            if !path.is_file() {
-                continue;
-            }
+        let output = fs::read_to_string(compiler.out_dir.join("index.html")).unwrap();
+        assert!(output.contains("<nav>v2</nav><main><p>Welcome</p></main>"));
 
-            let rel = match path.strip_prefix(&self.src_dir) {
-                Ok(p) => p.to_path_buf(),
-                Err(_) => continue,
-            };
+        let _ = fs::remove_dir_all(root);
+    }
 
-            if self.is_html_file(path) && !self.is_page_html(path) {
-                continue;
-            }
+    #[test]
+    fn builds_many_pages_in_parallel_with_ordered_results() {
+        let squares = parallel_map(&(0..500).collect::<Vec<u64>>(), |n| n * n);
+        assert_eq!(squares, (0..500).map(|n| n * n).collect::<Vec<u64>>());
+
+        let root = make_temp_dir("parallel-pages");
+        let compiler = make_compiler(&root);
+        fs::create_dir_all(compiler.src_dir.join("components")).unwrap();
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><body><include src="/components/nav.html" /><main slot="content"></main></body></html>"#,
+        )
+        .unwrap();
+        fs::write(compiler.src_dir.join("components/nav.html"), "<nav></nav>").unwrap();
+        for index in 0..60 {
+            fs::write(
+                compiler.src_dir.join(format!("page-{:02}.html", index)),
+                format!(r#"<main for-slot="content"><p>Page {}</p></main>"#, index),
+            )
+            .unwrap();
+            fs::write(
+                compiler.src_dir.join(format!("asset-{:02}.txt", index)),
+                "x",
+            )
+            .unwrap();
+        }
 
-            expected.insert(rel);
+        assert!(compiler.build_once(None));
+        for index in 0..60 {
+            let output =
+                fs::read_to_string(compiler.out_dir.join(format!("page-{:02}.html", index)))
+                    .unwrap();
+            assert!(output.contains(&format!("<nav></nav><main><p>Page {}</p></main>", index)));
+            assert!(compiler
+                .out_dir
+                .join(format!("asset-{:02}.txt", index))
+                .exists());
         }
 
-        expected
+        let _ = fs::remove_dir_all(root);
     }
 
-    fn file_hash_equal(&self, a: &Path, b: &Path) -> bool {
-        let hash_a = self.file_hash(a);
-        let hash_b = self.file_hash(b);
-        hash_a == hash_b
+    #[test]
+    fn classifies_pages_with_a_cached_text_scan() {
+        assert!(has_slot_providers(r#"<main FOR-SLOT="content"></main>"#));
+        assert!(!has_slot_providers(
+            r#"<!-- <main for-slot="content"></main> -->"#
+        ));
+        assert!(!has_slot_providers(
+            r#"<script>const html = '<main for-slot="content">';</script><div></div>"#
+        ));
+        assert!(!has_slot_providers("<p>Mentions for-slot in text</p>"));
+
+        let root = make_temp_dir("page-classification");
+        let compiler = make_compiler(&root);
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><body><main slot="content"></main></body></html>"#,
+        )
+        .unwrap();
+        let page = compiler.src_dir.join("index.html");
+        fs::write(&page, r#"<main for-slot="content"></main>"#).unwrap();
+        fs::write(compiler.src_dir.join("card.html"), "<div></div>").unwrap();
+        fs::write(compiler.src_dir.join("logo.svg"), "<svg></svg>").unwrap();
+
+        let sources = compiler.scan_source_files();
+        assert_eq!(sources.pages, vec![page.clone()]);
+        assert_eq!(
+            sources.fragments,
+            vec![
+                compiler.src_dir.join("_layout.html"),
+                compiler.src_dir.join("card.html")
+            ]
+        );
+        assert_eq!(sources.assets, vec![compiler.src_dir.join("logo.svg")]);
+        assert!(compiler.state.lock().unwrap().html_kinds[&page].2);
+
+        fs::write(&page, "<div>now a fragment</div>").unwrap();
+        assert!(!compiler.is_page_html(&page));
+
+        let _ = fs::remove_dir_all(root);
     }
 
-    fn file_hash(&self, path: &Path) -> Vec<u8> {
-        let mut hasher = Sha256::new();
-        if let Ok(mut file) = fs::File::open(path) {
-            let mut buffer = [0; 8192];
-            while let Ok(n) = file.read(&mut buffer) {
-                if n == 0 {
-                    break;
-                }
-                hasher.update(&buffer[..n]);
-            }
+    #[test]
+    fn explicit_rules_decide_pages_and_partials_and_warn_on_disagreement() {
+        let root = make_temp_dir("page-rules");
+        let mut compiler = make_compiler(&root);
+        compiler
+            .options
+            .page_globs
+            .push(SourceGlob::new("drafts/*.html").unwrap());
+        compiler
+            .options
+            .partial_globs
+            .push(SourceGlob::new("examples/**").unwrap());
+
+        for dir in ["components", "drafts", "examples", "_partials"] {
+            fs::create_dir_all(compiler.src_dir.join(dir)).unwrap();
         }
-        hasher.finalize().to_vec()
-    }
-}
+        let provider = r#"<main for-slot="content"></main>"#;
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><body><main slot="content"></main></body></html>"#,
+        )
+        .unwrap();
+        fs::write(compiler.src_dir.join("index.html"), provider).unwrap();
+        fs::write(compiler.src_dir.join("components/demo.html"), provider).unwrap();
+        fs::write(compiler.src_dir.join("_partials/nav.html"), provider).unwrap();
+        fs::write(compiler.src_dir.join("_footer.html"), "<footer></footer>").unwrap();
+        fs::write(compiler.src_dir.join("examples/slots.html"), provider).unwrap();
+        fs::write(compiler.src_dir.join("drafts/wip.html"), "<p>todo</p>").unwrap();
+        fs::write(
+            compiler.src_dir.join("card-list.html"),
+            r#"<include src="card.html"><h2 for-slot="title">Hi</h2></include>"#,
+        )
+        .unwrap();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::time::{SystemTime, UNIX_EPOCH};
+        let sources = compiler.scan_source_files();
+        assert_eq!(
+            sources.pages,
+            vec![
+                compiler.src_dir.join("drafts/wip.html"),
+                compiler.src_dir.join("index.html")
+            ]
+        );
+        assert!(compiler.is_component_html(&compiler.src_dir.join("card-list.html")));
+        assert_eq!(
+            sources
+                .warnings
+                .iter()
+                .map(|(_, warning)| warning.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "[Warn] _partials/nav.html has for-slot providers but is a partial (underscore prefix)",
+                "[Warn] components/demo.html has for-slot providers but is a partial (components/ folder)",
+                "[Warn] drafts/wip.html has no for-slot providers but is a page (--pages drafts/*.html)",
+                "[Warn] examples/slots.html has for-slot providers but is a partial (--partials examples/**)",
+            ]
+        );
 
-    fn make_temp_dir(name: &str) -> PathBuf {
-        let unique = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_nanos();
-        let dir = std::env::temp_dir().join(format!("html-slot-compiler-{name}-{unique}"));
-        fs::create_dir_all(&dir).unwrap();
-        dir
+        // Each warning is printed by the first build that sees it, not by every build.
+        compiler.build_once(None);
+        assert_eq!(
+            compiler.state.lock().unwrap().reported_warnings,
+            sources
+                .warnings
+                .iter()
+                .map(|(_, warning)| warning.clone())
+                .collect::<HashSet<_>>()
+        );
+
+        let _ = fs::remove_dir_all(root);
     }
 
-    fn make_compiler(root: &Path) -> Compiler {
-        let src_dir = root.join("src");
-        let out_dir = root.join("dist");
-        fs::create_dir_all(&src_dir).unwrap();
+    #[test]
+    fn passthrough_html_is_copied_as_is_or_with_includes() {
+        let root = make_temp_dir("passthrough");
+        let mut compiler = make_compiler(&root);
+        compiler
+            .options
+            .passthrough_globs
+            .push(SourceGlob::new("vendor/**").unwrap());
+
+        fs::create_dir_all(compiler.src_dir.join("vendor/demo")).unwrap();
+        fs::create_dir_all(compiler.src_dir.join("components")).unwrap();
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><body><main slot="content"></main></body></html>"#,
+        )
+        .unwrap();
+        fs::write(compiler.src_dir.join("components/nav.html"), "<nav></nav>").unwrap();
+        let verification = "google-site-verification: google123.html";
+        fs::write(compiler.src_dir.join("google123.static.html"), verification).unwrap();
+        let legacy = r#"<!DOCTYPE html><html><body><include src="/components/nav.html" /><p for-slot="x">demo</p></body></html>"#;
+        fs::write(compiler.src_dir.join("vendor/demo/index.html"), legacy).unwrap();
+        let latin1 = b"<p>caf\xe9</p>".to_vec();
+        fs::write(compiler.src_dir.join("vendor/latin1.html"), &latin1).unwrap();
 
-        Compiler {
-            src_dir: src_dir.clone(),
-            out_dir,
-        }
+        assert!(compiler.build_once(None));
+        assert_eq!(
+            fs::read_to_string(compiler.out_dir.join("google123.html")).unwrap(),
+            verification
+        );
+        assert!(!compiler.out_dir.join("google123.static.html").exists());
+        assert_eq!(
+            fs::read_to_string(compiler.out_dir.join("vendor/demo/index.html")).unwrap(),
+            legacy
+        );
+        assert_eq!(
+            fs::read(compiler.out_dir.join("vendor/latin1.html")).unwrap(),
+            latin1
+        );
+
+        // Expanding includes needs UTF-8 text.
+        compiler.options.passthrough_includes = true;
+        assert!(!compiler.build_once(None));
+        fs::remove_file(compiler.src_dir.join("vendor/latin1.html")).unwrap();
+        assert!(compiler.build_once(None));
+        let expanded = fs::read_to_string(compiler.out_dir.join("vendor/demo/index.html")).unwrap();
+        assert!(expanded.contains(r#"<body><nav></nav><p for-slot="x">demo</p></body>"#));
+        assert_eq!(
+            fs::read_to_string(compiler.src_dir.join("vendor/demo/index.html")).unwrap(),
+            legacy
+        );
+
+        let _ = fs::remove_dir_all(root);
     }
 
     #[test]
-    fn expands_recursive_includes_and_skips_component_output() {
-        let root = make_temp_dir("recursive-include");
+    fn static_html_writing_a_page_output_fails_the_build() {
+        let root = make_temp_dir("static-collision");
         let compiler = make_compiler(&root);
-
-        fs::create_dir_all(compiler.src_dir.join("components")).unwrap();
         fs::write(
-            &compiler.src_dir.join("_layout.html"),
-            r#"
-<!DOCTYPE html>
-<html>
-  <body>
-    <main slot="content"></main>
-  </body>
-</html>
-"#,
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><body><main slot="content"></main></body></html>"#,
+        )
+        .unwrap();
+        fs::write(
+            compiler.src_dir.join("about.html"),
+            r#"<main for-slot="content"><p>page</p></main>"#,
         )
         .unwrap();
+        let static_path = compiler.src_dir.join("about.static.html");
+        fs::write(&static_path, "<p>static</p>").unwrap();
+
+        let sources = compiler.scan_source_files();
+        assert!(sources.passthrough.is_empty());
+        assert_eq!(sources.collisions.len(), 1);
+        let error = &sources.collisions[0].1;
+        assert!(error.contains("about.html and about.static.html"));
+
+        assert!(!compiler.build_once(None));
+        let output = fs::read_to_string(compiler.out_dir.join("about.html")).unwrap();
+        assert!(output.contains("<p>page</p>"));
+        assert!(!output.contains("static"));
 
+        // An edit keeps the collision; removing the static file clears it.
+        assert!(!compiler.build_once(Some(&HashSet::from([static_path.clone()]))));
+        fs::remove_file(&static_path).unwrap();
+        assert!(compiler.build_once(Some(&HashSet::from([static_path]))));
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn build_cache_resumes_and_rebuilds_only_changed_pages() {
+        let root = make_temp_dir("build-cache");
+        let compiler = make_compiler(&root);
+        fs::create_dir_all(compiler.src_dir.join("components")).unwrap();
         fs::write(
-            compiler.src_dir.join("components/card.html"),
-            r#"<article class="card"><include src="badge.html" /></article>"#,
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><body><main slot="content"></main></body></html>"#,
         )
         .unwrap();
+        fs::write(compiler.src_dir.join("components/a.html"), "<p>A1</p>").unwrap();
+        fs::write(compiler.src_dir.join("components/b.html"), "<p>B1</p>").unwrap();
+        for (page, component) in [("one", "a"), ("two", "b")] {
+            fs::write(
+                compiler.src_dir.join(format!("{page}.html")),
+                format!(r#"<main for-slot="content"><include src="components/{component}.html" /></main>"#),
+            )
+            .unwrap();
+        }
+        assert!(compiler.build_once(None));
+        assert!(compiler.cache_path().is_file());
+        assert!(compiler.cache_path().starts_with(&root));
+        assert!(!compiler.cache_path().starts_with(&compiler.out_dir));
+
+        // A cache left in the output folder by an older version is cleaned up.
+        let leftover = compiler.out_dir.join(CACHE_FILE_NAME);
+        fs::write(&leftover, "site-compiler-cache 2").unwrap();
+        compiler.clean_output_dir(&compiler.scan_source_files());
+        assert!(!leftover.exists());
+
+        let included = |compiler: &Compiler, name: &str| {
+            let state = compiler.state.lock().unwrap();
+            state
+                .include_cache
+                .keys()
+                .any(|(path, _)| path.ends_with(name))
+        };
+
+        let restarted = make_compiler(&root);
+        assert_eq!(
+            restarted.resume_from_cache(&restarted.scan_source_files()),
+            Some(true)
+        );
+        assert!(restarted.state.lock().unwrap().include_cache.is_empty());
+
+        fs::write(compiler.src_dir.join("components/a.html"), "<p>A2</p>").unwrap();
+        let restarted = make_compiler(&root);
+        assert_eq!(
+            restarted.resume_from_cache(&restarted.scan_source_files()),
+            Some(true)
+        );
+        assert!(fs::read_to_string(compiler.out_dir.join("one.html"))
+            .unwrap()
+            .contains("A2"));
+        assert!(included(&restarted, "a.html"));
+        assert!(!included(&restarted, "b.html"));
+
+        fs::write(compiler.out_dir.join("two.html"), "edited by hand").unwrap();
+        let restarted = make_compiler(&root);
+        assert_eq!(
+            restarted.resume_from_cache(&restarted.scan_source_files()),
+            Some(true)
+        );
+        assert!(fs::read_to_string(compiler.out_dir.join("two.html"))
+            .unwrap()
+            .contains("B1"));
+        assert!(!included(&restarted, "a.html"));
+
+        let mut restarted = make_compiler(&root);
+        restarted.options.provenance = true;
+        assert_eq!(
+            restarted.resume_from_cache(&restarted.scan_source_files()),
+            None
+        );
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn build_cache_checks_include_targets_outside_the_source_folder() {
+        let root = make_temp_dir("cache-outside");
+        let mut compiler = make_compiler(&root);
+        compiler.options.include_roots = vec![PathBuf::from("../shared")];
+        fs::create_dir_all(root.join("shared")).unwrap();
         fs::write(
-            compiler.src_dir.join("components/badge.html"),
-            r#"<span class="badge">Included</span>"#,
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><body><main slot="content"></main></body></html>"#,
         )
         .unwrap();
+        fs::write(root.join("shared/note.html"), "<p>Note 1</p>").unwrap();
         fs::write(
             compiler.src_dir.join("index.html"),
-            r#"<main for-slot="content"><include src="components/card.html" /></main>"#,
+            r#"<main for-slot="content"><include src="note.html" /></main>"#,
         )
         .unwrap();
-
         assert!(compiler.build_once(None));
 
-        let built = fs::read_to_string(compiler.out_dir.join("index.html")).unwrap();
-        assert!(built
-            .contains(r#"<article class="card"><span class="badge">Included</span></article>"#));
-        assert!(!compiler.out_dir.join("components/card.html").exists());
-        assert!(!compiler.out_dir.join("components/badge.html").exists());
+        let restart = || {
+            let mut restarted = make_compiler(&root);
+            restarted.options.include_roots = vec![PathBuf::from("../shared")];
+            restarted
+        };
+        let restarted = restart();
+        assert_eq!(
+            restarted.resume_from_cache(&restarted.scan_source_files()),
+            Some(true)
+        );
+        assert!(restarted.state.lock().unwrap().include_cache.is_empty());
+
+        fs::write(root.join("shared/note.html"), "<p>Note 2</p>").unwrap();
+        let restarted = restart();
+        assert_eq!(
+            restarted.resume_from_cache(&restarted.scan_source_files()),
+            Some(true)
+        );
+        assert!(fs::read_to_string(compiler.out_dir.join("index.html"))
+            .unwrap()
+            .contains("Note 2"));
 
         let _ = fs::remove_dir_all(root);
     }
 
     #[test]
-    fn reports_include_cycles() {
-        let root = make_temp_dir("include-cycle");
-        let compiler = make_compiler(&root);
-
+    fn strict_unused_fails_resumed_builds_too() {
+        let root = make_temp_dir("cache-strict-unused");
+        let strict = || {
+            let mut compiler = make_compiler(&root);
+            compiler.options.report_unused = true;
+            compiler.options.strict_unused = true;
+            compiler
+        };
+        let compiler = strict();
         fs::create_dir_all(compiler.src_dir.join("components")).unwrap();
-        let a_path = compiler.src_dir.join("components/a.html");
-        let b_path = compiler.src_dir.join("components/b.html");
+        fs::write(
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><body><main slot="content"></main></body></html>"#,
+        )
+        .unwrap();
+        let page = compiler.src_dir.join("index.html");
+        fs::write(&page, r#"<main for-slot="content">Hi</main>"#).unwrap();
+        fs::write(compiler.src_dir.join("components/old.html"), "<p>Old</p>").unwrap();
+        assert!(!compiler.build_once(None));
+
+        // Up to date according to the cache.
+        let restarted = strict();
+        assert_eq!(
+            restarted.resume_from_cache(&restarted.scan_source_files()),
+            Some(false)
+        );
 
-        fs::write(&a_path, r#"<include src="b.html" />"#).unwrap();
-        fs::write(&b_path, r#"<include src="a.html" />"#).unwrap();
+        // A page edit resumes with an incremental build.
+        fs::write(&page, r#"<main for-slot="content">Hello</main>"#).unwrap();
+        let restarted = strict();
+        assert_eq!(
+            restarted.resume_from_cache(&restarted.scan_source_files()),
+            Some(false)
+        );
 
-        let error = compiler.expand_includes_in_file(&a_path).unwrap_err();
-        assert!(error.contains("Include cycle detected"));
-        assert!(error.contains("a.html"));
-        assert!(error.contains("b.html"));
+        fs::remove_file(compiler.src_dir.join("components/old.html")).unwrap();
+        let restarted = strict();
+        assert_eq!(
+            restarted.resume_from_cache(&restarted.scan_source_files()),
+            Some(true)
+        );
 
         let _ = fs::remove_dir_all(root);
     }
 
     #[test]
-    fn attr_slot_mode_updates_meta_content_without_mangling_tag() {
-        let root = make_temp_dir("attr-slot-meta");
-        let compiler = make_compiler(&root);
-
+    fn output_cleanup_keeps_only_the_cache_in_use() {
+        let root = make_temp_dir("cache-dir");
+        let mut compiler = make_compiler(&root);
+        compiler.options.cache_dir = Some(compiler.out_dir.join("cache"));
         fs::write(
-            &compiler.src_dir.join("_layout.html"),
-            r#"
-<!DOCTYPE html>
-<html>
-  <head>
-    <meta slot="description" slot-mode="attr:content" content="" />
-  </head>
-  <body>
-    <main slot="content"></main>
-  </body>
-</html>
-"#,
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><body><main slot="content"></main></body></html>"#,
         )
         .unwrap();
-
         fs::write(
             compiler.src_dir.join("index.html"),
-            r#"
-<meta for-slot="description" content="Synthetic benchmark page 001" />
-<main for-slot="content"><p>Hello</p></main>
-"#,
+            r#"<main for-slot="content">Hi</main>"#,
         )
         .unwrap();
-
+        fs::create_dir_all(&compiler.out_dir).unwrap();
+        let stale = compiler.out_dir.join(CACHE_FILE_NAME);
+        fs::write(&stale, "site-compiler-cache 1").unwrap();
         assert!(compiler.build_once(None));
 
-        let built = fs::read_to_string(compiler.out_dir.join("index.html")).unwrap();
-        assert!(built.contains(r#"<meta content="Synthetic benchmark page 001" />"#));
-        assert!(!built.contains(r#"/ content=""#));
-        assert_eq!(built.matches("content=").count(), 1);
+        let sources = compiler.scan_source_files();
+        compiler.clean_output_dir(&sources);
+        assert!(!stale.exists());
+        assert!(compiler.cache_path().is_file());
+        assert_eq!(compiler.resume_from_cache(&sources), Some(true));
 
         let _ = fs::remove_dir_all(root);
     }
 
     #[test]
-    fn builds_nested_pages_with_the_closest_layout() {
-        let root = make_temp_dir("nested-layout");
+    fn watch_builds_leave_former_pages_alone() {
+        let root = make_temp_dir("former-page");
         let compiler = make_compiler(&root);
-        let blog_dir = compiler.src_dir.join("blog");
-        let post_dir = blog_dir.join("posts");
-        fs::create_dir_all(&post_dir).unwrap();
-
+        fs::create_dir_all(compiler.src_dir.join("components")).unwrap();
         fs::write(
-            &compiler.src_dir.join("_layout.html"),
-            r#"
-<!DOCTYPE html>
-<html>
-  <body>
-    <header slot="header"></header>
-    <main slot="content"></main>
-  </body>
-</html>
-"#,
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><body><main slot="content"></main></body></html>"#,
         )
         .unwrap();
+        fs::write(compiler.src_dir.join("components/nav.html"), "<nav></nav>").unwrap();
+        let about = compiler.src_dir.join("about.html");
+        fs::write(
+            &about,
+            r#"<main for-slot="content"><include src="components/nav.html" /></main>"#,
+        )
+        .unwrap();
+        assert!(compiler.build_once(None));
 
+        let partial = r#"<section>now a partial</section><include src="components/nav.html" />"#;
+        fs::write(&about, partial).unwrap();
+        assert!(compiler.build_once(Some(&HashSet::from([about.clone()]))));
+        assert_eq!(fs::read_to_string(&about).unwrap(), partial);
+
+        // The component no longer rebuilds the former page either.
+        let nav = compiler.src_dir.join("components/nav.html");
+        fs::write(&nav, "<nav>v2</nav>").unwrap();
+        assert!(compiler.build_once(Some(&HashSet::from([nav]))));
+        assert_eq!(fs::read_to_string(&about).unwrap(), partial);
+        assert!(!compiler
+            .state
+            .lock()
+            .unwrap()
+            .deps
+            .page_layouts
+            .contains_key(&about.canonicalize().unwrap()));
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn watch_builds_reuse_the_previous_scan_for_edited_files() {
+        let root = make_temp_dir("rescan");
+        let compiler = make_compiler(&root);
         fs::write(
-            blog_dir.join("_layout.html"),
-            r#"
-<!DOCTYPE html>
-<html>
-  <body class="blog-shell">
-    <aside slot="header"></aside>
-    <article slot="content"></article>
-  </body>
-</html>
-"#,
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><body><main slot="content"></main></body></html>"#,
         )
         .unwrap();
+        let page = compiler.src_dir.join("index.html");
+        fs::write(&page, r#"<main for-slot="content">Hi</main>"#).unwrap();
+        fs::write(compiler.src_dir.join("site.css"), "body{}").unwrap();
+        assert!(compiler.build_once(None));
+
+        let edited = HashSet::from([page.clone()]);
+        let reused = compiler.rescan_changed(&edited).unwrap();
+        assert_eq!(reused.pages, vec![page.clone()]);
+        assert_eq!(reused.assets, vec![compiler.src_dir.join("site.css")]);
+
+        fs::write(&page, "<p>no providers</p>").unwrap();
+        let reused = compiler.rescan_changed(&edited).unwrap();
+        assert!(reused.pages.is_empty());
+        assert!(reused.fragments.contains(&page));
+
+        let added = compiler.src_dir.join("about.html");
+        fs::write(&added, r#"<main for-slot="content">About</main>"#).unwrap();
+        assert!(compiler.rescan_changed(&HashSet::from([added])).is_none());
+
+        let _ = fs::remove_dir_all(root);
+    }
 
+    #[test]
+    fn asset_copies_are_checked_by_size_and_time_before_hashing() {
+        let root = make_temp_dir("asset-stamps");
+        let compiler = make_compiler(&root);
+        fs::create_dir_all(compiler.src_dir.join("img")).unwrap();
         fs::write(
-            post_dir.join("post.html"),
-            r#"
-<section for-slot="header"><h1>Nested Post</h1></section>
-<section for-slot="content"><p>Rendered with the nearest layout.</p></section>
-"#,
+            compiler.src_dir.join("_layout.html"),
+            r#"<html><body><main slot="content"></main></body></html>"#,
         )
         .unwrap();
+        let page = compiler.src_dir.join("index.html");
+        fs::write(&page, r#"<main for-slot="content">Hi</main>"#).unwrap();
+        let logo = compiler.src_dir.join("img/logo.png");
+        fs::write(&logo, "LOGO-1").unwrap();
+        assert!(compiler.build_once(None));
 
+        let copy = compiler.out_dir.join("img/logo.png");
+        assert_eq!(fs::read_to_string(&copy).unwrap(), "LOGO-1");
+
+        // A copy with a different size is replaced on the next full build.
+        fs::write(&copy, "edited").unwrap();
         assert!(compiler.build_once(None));
+        assert_eq!(fs::read_to_string(&copy).unwrap(), "LOGO-1");
 
-        let built = fs::read_to_string(compiler.out_dir.join("blog/posts/post.html")).unwrap();
-        assert!(built.contains(r#"<body class="blog-shell">"#));
-        assert!(built.contains(r#"<aside><h1>Nested Post</h1></aside>"#));
-        assert!(built.contains(r#"<article><p>Rendered with the nearest layout.</p></article>"#));
+        assert!(compiler.build_once(Some(&HashSet::from([page.clone()]))));
+        fs::write(&logo, "LOGO-2").unwrap();
+        assert!(compiler.build_once(Some(&HashSet::from([logo.clone()]))));
+        assert_eq!(fs::read_to_string(&copy).unwrap(), "LOGO-2");
+
+        let restarted = make_compiler(&root);
+        assert_eq!(
+            restarted.resume_from_cache(&restarted.scan_source_files()),
+            Some(true)
+        );
+        let state = restarted.state.lock().unwrap();
+        assert_eq!(
+            state.asset_copies[Path::new("img/logo.png")].hash,
+            Sha256::digest(b"LOGO-2").to_vec()
+        );
+        assert!(state.file_stamps.contains_key(Path::new("index.html")));
+        drop(state);
 
         let _ = fs::remove_dir_all(root);
     }